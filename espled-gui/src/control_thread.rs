@@ -1,70 +1,206 @@
 use std::{
-    collections::HashMap,
-    fmt::write,
-    io::{BufRead, BufReader, Read},
+    collections::BTreeMap,
+    net::SocketAddr,
     sync::{
         mpsc::{self, Receiver, Sender},
         Arc,
-    }, thread, time::Duration
+    },
+    thread,
 };
 
-use protocol::{ParameterTypes, Request};
-use serde::de::DeserializeOwned;
-use serialport::{self, SerialPort, SerialPortInfo};
-use std::sync::Mutex;
+use anyhow::Context;
+use protocol::client::{Client, ClientError};
+use protocol::{
+    Capability, DeviceInfo, EffectInfo, ErrorCode, Event, EventKind, ParameterDescriptor,
+    ParameterTypes, PowerState, VersionInfo,
+};
+use serialport::{self, SerialPortInfo};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::tokens::TokenStore;
 
 #[derive(Debug, PartialEq)]
 pub enum Command {
     ProbeControllersOnSerials,
+    ConnectToAddress(SocketAddr),
+    /// Code for the controller in [`ChannelStatus::AwaitingPairingCode`]
+    Pair(u32),
+    CancelPairing,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum ChannelStatus {
     ProbingControllers(String),
     NoControllers,
+    /// The controller at `address` shows a pairing code, answer with [`ControlChannel::pair`].
+    /// `error` tells why the previous code didn't work
+    AwaitingPairingCode {
+        address: String,
+        error: Option<String>,
+    },
+    Failed(String),
     Done,
 }
 
+/// How a controller is reached
+#[derive(Clone, Debug, PartialEq)]
+pub enum Link {
+    Serial(SerialPortInfo),
+    /// TCP, requests carry the token issued when pairing, see [`TokenStore`]
+    Network(SocketAddr),
+}
+
+/// Result of [`connect_to_address`]
+enum Connection {
+    Ready(Controller),
+    /// Pairing started, the client waits for the code
+    NeedsPairing(Client),
+}
+
 #[derive(Clone, Debug)]
 pub struct Controller {
     pub name: String,
-    pub serial_port: SerialPortInfo,
-    pub options: HashMap<String, ParameterTypes>,
-    pub effect_list: Vec<String>,
+    pub link: Link,
+    pub options: Vec<ParameterDescriptor>,
+    pub effects: Vec<EffectInfo>,
+    pub version: VersionInfo,
+    /// `None` when the firmware has no power control
+    pub power: Option<PowerState>,
+    /// [`EffectInfo::id`]
     selected_effect: String,
+    client: Arc<Client>,
 }
 
 impl Controller {
-    pub fn set_effect<S: Into<String>>(&mut self, effect_name: S) {
-        self.selected_effect = effect_name.into();
-        let index = self
-            .effect_list
-            .iter()
-            .position(|x| *x == self.selected_effect)
-            .unwrap_or_default();
-        serial_request(self.serial_port.clone(), &Request::SetEffect(index));
-        self.options = serial_request_wait_response(
-            self.serial_port.clone(),
-            &protocol::Request::GetParameters,
-        )
-        .unwrap();
+    /// Applies events pushed by the controller to the cached state and returns them
+    pub fn poll_events(&mut self) -> Vec<Event> {
+        let events = self.client.events();
+        for event in events.iter() {
+            match event {
+                Event::EffectChanged { id, parameters, .. } => {
+                    self.selected_effect = id.clone();
+                    self.options = parameters.clone();
+                }
+                Event::ParameterChanged { key, value } => {
+                    if let Some(descriptor) = self
+                        .options
+                        .iter_mut()
+                        .find(|descriptor| descriptor.key == *key)
+                    {
+                        descriptor.value = value.clone();
+                    }
+                }
+                Event::PowerChanged(on) => {
+                    if let Some(power) = &mut self.power {
+                        power.on = *on;
+                    }
+                }
+                Event::BrightnessChanged(brightness) => {
+                    if let Some(power) = &mut self.power {
+                        power.brightness = *brightness;
+                    }
+                }
+                Event::Error(err) => log::warn!("{} reported: {err}", self.name),
+                _ => log::debug!("{}: {event:?}", self.name),
+            }
+        }
+        events
+    }
+
+    pub fn set_effect<S: Into<String>>(&mut self, effect_id: S) -> anyhow::Result<()> {
+        let effect_id = effect_id.into();
+        if self.supports(Capability::EffectIds) {
+            self.client.set_effect_by_id(effect_id.as_str())?;
+        } else {
+            // without IDs the list is addressed by position, see `probe_controller_on_serial_port`
+            let index = self
+                .effects
+                .iter()
+                .position(|effect| effect.id == effect_id)
+                .with_context(|| format!("{} has no effect {effect_id}", self.name))?;
+            self.client.set_effect(index)?;
+        }
+        self.selected_effect = effect_id;
+        self.options = self.client.get_parameters()?;
+        Ok(())
     }
 
     pub fn get_effect(&self) -> String {
         self.selected_effect.clone()
     }
 
-    pub fn set_options(&self) {
-        for (option, value) in self.options.iter() {
-            let response: Option<bool> = serial_request_wait_response(
-                self.serial_port.clone(),
-                &Request::SetOption(option.clone(), *value),
-            );
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.version.supports(capability)
+    }
+
+    pub fn set_power(&mut self, on: bool) -> anyhow::Result<()> {
+        let on = self
+            .client
+            .set_power(on)
+            .context("unable to switch power")?;
+        if let Some(power) = &mut self.power {
+            power.on = on;
+        }
+        Ok(())
+    }
+
+    /// Stores the brightness the controller actually applied
+    pub fn set_brightness(&mut self, brightness: f32) -> anyhow::Result<()> {
+        let brightness = self
+            .client
+            .set_brightness(brightness)
+            .context("unable to set brightness")?;
+        if let Some(power) = &mut self.power {
+            power.brightness = brightness;
+        }
+        Ok(())
+    }
+
+    pub fn device_info(&self) -> anyhow::Result<DeviceInfo> {
+        self.client
+            .get_device_info()
+            .context("unable to read device info")
+    }
 
-            if !response.unwrap_or(false) {
-                log::error!("Unable to setup option {option}!");
+    /// Sends every option, then stores the values the controller actually applied
+    pub fn set_options(&mut self) -> anyhow::Result<()> {
+        if self.supports(Capability::BatchParameters) {
+            let values = self
+                .options
+                .iter()
+                .map(|descriptor| (descriptor.key.clone(), descriptor.value.clone()))
+                .collect();
+            let mut results: BTreeMap<String, Result<ParameterTypes, protocol::Error>> = self
+                .client
+                .set_options(values)
+                .context("unable to set options")?;
+
+            let mut errors = Vec::new();
+            for descriptor in self.options.iter_mut() {
+                match results.remove(&descriptor.key) {
+                    Some(Ok(value)) => descriptor.value = value,
+                    // rejected values are the interesting ones, the rest just went with them
+                    Some(Err(err)) if err.code != ErrorCode::Aborted => {
+                        errors.push(format!("{}: {err}", descriptor.label))
+                    }
+                    _ => {}
+                }
             }
+            if !errors.is_empty() {
+                anyhow::bail!("unable to set options\n{}", errors.join("\n"));
+            }
+            return Ok(());
+        }
+
+        for index in 0..self.options.len() {
+            let descriptor = &self.options[index];
+            let value = self
+                .client
+                .set_option(descriptor.key.clone(), descriptor.value.clone())
+                .with_context(|| format!("unable to set option {}", descriptor.label))?;
+            self.options[index].value = value;
         }
+        Ok(())
     }
 }
 
@@ -84,6 +220,10 @@ impl std::fmt::Display for ChannelStatus {
             ChannelStatus::NoControllers => {
                 write!(f, "No COM/Serial Ports controllers are present in system")
             }
+            ChannelStatus::AwaitingPairingCode { address, .. } => {
+                write!(f, "Waiting for the pairing code of: {address}")
+            }
+            ChannelStatus::Failed(message) => write!(f, "{message}"),
         }
     }
 }
@@ -100,10 +240,13 @@ impl ControlChannel {
         let (tx, rx): (Sender<Command>, Receiver<Command>) = mpsc::channel();
         let (status_tx, status_rx): (Sender<ChannelStatus>, Receiver<ChannelStatus>) =
             mpsc::channel();
-        let controllers = Arc::new(Mutex::new(Vec::new()));
+        let controllers: Arc<Mutex<Vec<Controller>>> = Arc::new(Mutex::new(Vec::new()));
 
         let controller_clone = controllers.clone();
         let status_tx_clone = status_tx.clone();
+        let mut tokens = TokenStore::load();
+        // controller waiting for its pairing code, with its address
+        let mut pending: Option<(SocketAddr, Client)> = None;
 
         thread::spawn(move || loop {
             match rx.recv() {
@@ -111,20 +254,34 @@ impl ControlChannel {
                     Command::ProbeControllersOnSerials => {
                         let ports = serialport::available_ports().unwrap();
                         for p in ports {
+                            // the port of a known controller is already open
+                            if controller_clone
+                                .lock()
+                                .unwrap()
+                                .iter()
+                                .any(|controller| controller.link == Link::Serial(p.clone()))
+                            {
+                                continue;
+                            }
                             let _ = status_tx_clone
                                 .send(ChannelStatus::ProbingControllers(p.clone().port_name))
                                 .unwrap();
 
-                            if let Some(controller) = probe_controller_on_serial_port(p.clone()) {
-                                let mut controller_lock = controller_clone.lock().unwrap();
-                                if controller_lock
-                                    .iter()
-                                    .find(|x: &&Controller| x.serial_port == p)
-                                    .is_none()
-                                {
-                                    controller_lock.push(controller);
+                            match probe_controller_on_serial_port(p.clone()) {
+                                Ok(controller) => {
+                                    let mut controller_lock = controller_clone.lock().unwrap();
+                                    if controller_lock
+                                        .iter()
+                                        .find(|x: &&Controller| x.link == Link::Serial(p.clone()))
+                                        .is_none()
+                                    {
+                                        controller_lock.push(controller);
+                                    }
+                                    drop(controller_lock);
+                                }
+                                Err(err) => {
+                                    log::warn!("no controller on {}: {err:#}", p.port_name);
                                 }
-                                drop(controller_lock);
                             }
                         }
                         let controller_lock = controller_clone.lock().unwrap();
@@ -135,6 +292,70 @@ impl ControlChannel {
                         }
                         drop(controller_lock);
                     }
+                    Command::ConnectToAddress(address) => {
+                        pending = None;
+                        let link = Link::Network(address);
+                        if controller_clone
+                            .lock()
+                            .unwrap()
+                            .iter()
+                            .any(|controller| controller.link == link)
+                        {
+                            status_tx_clone.send(ChannelStatus::Done).unwrap();
+                            continue;
+                        }
+                        status_tx_clone
+                            .send(ChannelStatus::ProbingControllers(address.to_string()))
+                            .unwrap();
+
+                        let status = match connect_to_address(address, &mut tokens) {
+                            Ok(Connection::Ready(controller)) => {
+                                controller_clone.lock().unwrap().push(controller);
+                                ChannelStatus::Done
+                            }
+                            Ok(Connection::NeedsPairing(client)) => {
+                                pending = Some((address, client));
+                                ChannelStatus::AwaitingPairingCode {
+                                    address: address.to_string(),
+                                    error: None,
+                                }
+                            }
+                            Err(err) => ChannelStatus::Failed(format!("{err:#}")),
+                        };
+                        status_tx_clone.send(status).unwrap();
+                    }
+                    Command::Pair(code) => {
+                        let Some((address, mut client)) = pending.take() else {
+                            continue;
+                        };
+                        let status = match client.pair(code) {
+                            Ok(token) => {
+                                if let Err(err) = tokens.insert(&address, token) {
+                                    log::warn!("{err:#}, {address} has to be paired again");
+                                }
+                                match read_controller(client, Link::Network(address)) {
+                                    Ok(controller) => {
+                                        controller_clone.lock().unwrap().push(controller);
+                                        ChannelStatus::Done
+                                    }
+                                    Err(err) => ChannelStatus::Failed(format!("{err:#}")),
+                                }
+                            }
+                            // the controller allows a few tries per code
+                            Err(err) => {
+                                pending = Some((address, client));
+                                ChannelStatus::AwaitingPairingCode {
+                                    address: address.to_string(),
+                                    error: Some(err.to_string()),
+                                }
+                            }
+                        };
+                        status_tx_clone.send(status).unwrap();
+                    }
+                    Command::CancelPairing => {
+                        pending = None;
+                        status_tx_clone.send(ChannelStatus::Done).unwrap();
+                    }
                 },
                 Err(_) => {
                     break;
@@ -156,17 +377,31 @@ impl ControlChannel {
             .unwrap();
     }
 
-    pub fn get_controllers(&self) -> Vec<Controller> {
-        let lock = self.controllers.try_lock();
-        match lock {
-            Ok(t) => return t.clone(),
-            Err(err) => {
-                log::trace!("cant lock: {err}");
-                return Vec::new();
-            }
+    pub fn connect(&self, address: SocketAddr) {
+        self.sender
+            .send(Command::ConnectToAddress(address))
+            .unwrap();
+    }
+
+    /// Answers [`ChannelStatus::AwaitingPairingCode`]
+    pub fn pair(&mut self, code: u32) {
+        self.sender.send(Command::Pair(code)).unwrap();
+        // so the code isn't asked for again before the thread picks it up
+        if let ChannelStatus::AwaitingPairingCode { address, .. } = &self.last_status {
+            self.last_status = ChannelStatus::ProbingControllers(address.clone());
         }
     }
 
+    pub fn cancel_pairing(&mut self) {
+        self.sender.send(Command::CancelPairing).unwrap();
+        self.last_status = ChannelStatus::Done;
+    }
+
+    /// Controllers found so far. They are never removed, so an index keeps pointing at the same one
+    pub fn controllers(&self) -> MutexGuard<'_, Vec<Controller>> {
+        self.controllers.lock().unwrap()
+    }
+
     pub fn acknown_status(&mut self) {
         self.last_status = ChannelStatus::Done;
     }
@@ -180,90 +415,95 @@ impl ControlChannel {
     }
 }
 
-fn serial_request(p: SerialPortInfo, request: &impl serde::Serialize) -> bool {
-    let request_json = serde_json::to_string(request).unwrap();
-    log::debug!("← {}", request_json);
-
-    if let Ok(mut port) = serialport::new(p.port_name.clone(), 115200)
-        .timeout(Duration::from_millis(1000))
-        .open()
-    {
-        let result = port.write_all(format!("{request_json}\n").as_bytes());
-        port.flush();
-
-        log::debug!("→ {}: {}", port.name().unwrap_or_default(), result.is_ok());
-        return result.is_ok();
-    } else {
-        return false;
-    }
+pub fn probe_controller_on_serial_port(p: SerialPortInfo) -> anyhow::Result<Controller> {
+    let mut client = Client::open_serial(&p.port_name)
+        .with_context(|| format!("cannot open port: {}", p.port_name))?;
+    handshake(&mut client)?;
+    read_controller(client, Link::Serial(p))
 }
 
-fn serial_request_wait_response<T>(p: SerialPortInfo, request: &impl serde::Serialize) -> Option<T>
-where
-    T: DeserializeOwned,
-{
-    let request_json = serde_json::to_string(request).unwrap();
-    log::debug!("← {}", request_json);
-
-    if let Ok(mut port) = serialport::new(p.port_name.clone(), 115200)
-        .timeout(Duration::from_millis(1000))
-        .open()
-    {
-        let mut fails = 0;
-
-        loop {
-            let _ = port.write_all(format!("{request_json}\n").as_bytes());
-            let _ = port.flush();
-            let mut reader = BufReader::new(port.try_clone().expect("Failed to clone port"));
-            let mut response_string = String::new();
-
-            match reader.read_line(&mut response_string) {
-                Ok(_) => {
-                    log::debug!("→ {}: {}", port.name().unwrap_or_default(), response_string);
-
-                    if let Ok(response) = serde_json::from_str::<T>(&response_string) {
-                        return Some(response);
-                    } else {
-                        log::warn!(
-                            "invalid JSON sequence from: {}, excepted json - got: {}",
-                            p.port_name,
-                            response_string
-                        );
-                        fails += 1;
-                    }
+/// Connects with the stored token, pairing starts when there is none or the controller forgot it
+fn connect_to_address(address: SocketAddr, tokens: &mut TokenStore) -> anyhow::Result<Connection> {
+    let open = |token| -> anyhow::Result<Client> {
+        let mut client =
+            Client::connect_tcp(address).with_context(|| format!("cannot connect to {address}"))?;
+        if let Some(token) = token {
+            client = client.with_token(token);
+        }
+        handshake(&mut client)?;
+        Ok(client)
+    };
+
+    let mut client = open(tokens.get(&address))?;
+    if client.supports(Capability::Pairing) {
+        if client.token().is_some() {
+            match client.get_name() {
+                Err(ClientError::Controller(err)) if err.code == ErrorCode::Unauthorized => {
+                    log::warn!("{address} no longer accepts our token, pairing again");
+                    tokens.remove(&address)?;
+                    client = open(None)?;
                 }
-                Err(err) => {
-                    log::warn!("failed to read from: {} error: {}", p.port_name, err);
-                    fails += 1;
+                result => {
+                    result?;
                 }
             }
-
-            if fails >= 5 {
-                log::warn!("failed to read after {fails} tries, giving up");
-                break;
-            }
         }
-    } else {
-        log::error!("Cannot open port: {}", p.port_name);
+        if client.token().is_none() {
+            client.start_pairing().context("unable to start pairing")?;
+            return Ok(Connection::NeedsPairing(client));
+        }
     }
+    let controller = read_controller(client, Link::Network(address))?;
+    Ok(Connection::Ready(controller))
+}
 
-    None
+fn handshake(client: &mut Client) -> anyhow::Result<()> {
+    match client.handshake() {
+        Ok(_) => Ok(()),
+        Err(err @ ClientError::Incompatible(_)) => Err(err.into()),
+        Err(err) => Err(anyhow::Error::new(err)
+            .context("handshake failed, firmware is too old or not an espled controller")),
+    }
 }
 
-pub fn probe_controller_on_serial_port(p: SerialPortInfo) -> Option<Controller> {
-    let name: String = serial_request_wait_response(p.clone(), &protocol::Request::GetName)?;
-    let options: HashMap<String, ParameterTypes> =
-        serial_request_wait_response(p.clone(), &protocol::Request::GetParameters)?;
-    let selected_effect: String =
-        serial_request_wait_response(p.clone(), &protocol::Request::GetEffect)?;
-    let effect_list: Vec<String> =
-        serial_request_wait_response(p.clone(), &protocol::Request::GetEffects)?;
-
-    Some(Controller {
-        name,
-        options,
+/// Reads the state of a controller after the handshake
+fn read_controller(client: Client, link: Link) -> anyhow::Result<Controller> {
+    let version = client.version().cloned().context("no handshake")?;
+
+    // subscribe before reading the state, so no change in between is missed
+    if client.supports(Capability::Events) {
+        client.subscribe(EventKind::ALL.to_vec())?;
+    }
+
+    // older firmware has no IDs, the names stand in for them
+    let (effects, selected_effect) = if client.supports(Capability::EffectIds) {
+        (client.list_effects()?, client.get_effect_id()?)
+    } else {
+        let effects = client
+            .get_effects()?
+            .into_iter()
+            .map(|name| EffectInfo {
+                id: name.clone(),
+                name,
+            })
+            .collect();
+        (effects, client.get_effect()?)
+    };
+
+    let power = if client.supports(Capability::PowerControl) {
+        Some(client.get_power_state()?)
+    } else {
+        None
+    };
+
+    Ok(Controller {
+        name: client.get_name()?,
+        power,
+        options: client.get_parameters()?,
         selected_effect,
-        effect_list,
-        serial_port: p.clone(),
+        effects,
+        version,
+        link,
+        client: Arc::new(client),
     })
 }
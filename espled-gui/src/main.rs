@@ -2,15 +2,19 @@ use crate::egui::FontFamily::Proportional;
 use crate::egui::TextStyle::Heading;
 use crate::egui::TextStyle::Name;
 use std::collections::BTreeMap;
+use std::time::Duration;
 
 use control_thread::{ChannelStatus, ControlChannel, Controller};
 use eframe::egui::{self, menu, vec2, FontId};
 use egui_extras::{Column, TableBuilder};
+use protocol::{Capability, Event};
 use views::{
-    connection::ConnectionView, editor::EditorView, message::Message, ToggledViewManager, View,
+    connection::ConnectionView, device_info::DeviceInfoView, editor::EditorView,
+    message::Message, ToggledViewManager, View,
 };
 
 pub mod control_thread;
+pub mod tokens;
 pub mod views;
 
 fn main() {
@@ -29,30 +33,85 @@ fn main() {
 
 struct MyEguiApp {
     connection_view: ToggledViewManager,
+    device_info_view: ToggledViewManager,
     editor_view: EditorView,
     control_thread: ControlChannel,
-    selected_controller: Option<Controller>,
+    /// Index into [`ControlChannel::controllers`], which holds the state events are applied to
+    selected_controller: Option<usize>,
+    error_message: Option<String>,
 }
 
 impl MyEguiApp {
     fn new(cc: &eframe::CreationContext<'_>, control: ControlChannel) -> Self {
         Self {
             connection_view: ToggledViewManager::new(Box::new(ConnectionView::default())),
+            device_info_view: ToggledViewManager::new(Box::new(DeviceInfoView::default())),
             editor_view: EditorView::default(),
             control_thread: control,
             selected_controller: None,
+            error_message: None,
         }
     }
 
     fn process(&mut self, ctx: &egui::Context) {
         let connect_view = self
             .connection_view
-            .as_original::<ConnectionView>()
+            .as_original_mut::<ConnectionView>()
             .unwrap();
         if connect_view.connect_button_clicked {
-            // TODO
+            connect_view.connect_button_clicked = false;
+            if let Ok(address) = connect_view.get_address() {
+                self.control_thread.connect(address);
+            }
+            self.connection_view.enabled = false;
+        } else if connect_view.pair_button_clicked {
+            connect_view.pair_button_clicked = false;
+            connect_view.pairing_with = None;
+            if let Some(code) = connect_view.take_code() {
+                self.control_thread.pair(code);
+            }
             self.connection_view.enabled = false;
         }
+
+        let device_info_view = self
+            .device_info_view
+            .as_original::<DeviceInfoView>()
+            .unwrap();
+        if device_info_view.refresh_clicked {
+            self.refresh_device_info();
+        }
+    }
+
+    /// Runs `f` on the selected controller, `None` when there is none
+    fn with_selected<R>(&self, f: impl FnOnce(&mut Controller) -> R) -> Option<R> {
+        let mut controllers = self.control_thread.controllers();
+        self.selected_controller
+            .and_then(|index| controllers.get_mut(index))
+            .map(f)
+    }
+
+    /// Asks the selected controller again, uptime and heap change all the time
+    fn refresh_device_info(&mut self) {
+        let result = self.with_selected(|controller| {
+            controller
+                .supports(Capability::DeviceInfo)
+                .then(|| controller.device_info())
+        });
+        let info = match result.flatten() {
+            Some(Ok(info)) => Some(info),
+            Some(Err(err)) => {
+                log::error!("{err:#}");
+                self.error_message = Some(format!("{err:#}"));
+                None
+            }
+            None => None,
+        };
+        let view = self
+            .device_info_view
+            .as_original_mut::<DeviceInfoView>()
+            .unwrap();
+        view.info = info;
+        view.refresh_clicked = false;
     }
 }
 
@@ -68,21 +127,52 @@ impl eframe::App for MyEguiApp {
             .max_width(400.0)
             .show(ctx, |ui| {
                 menu::bar(ui, |ui| {
-                    ui.menu_button("Tools", |ui| if ui.button("Serial monitor").clicked() {});
+                    ui.menu_button("Tools", |ui| {
+                        let supported = self
+                            .with_selected(|controller| controller.supports(Capability::DeviceInfo))
+                            .unwrap_or(false);
+                        if ui
+                            .add_enabled(supported, egui::Button::new("Device info"))
+                            .on_disabled_hover_text("Needs a controller with newer firmware")
+                            .clicked()
+                        {
+                            self.device_info_view.enabled = true;
+                            self.refresh_device_info();
+                            ui.close_menu();
+                        }
+                        if ui.button("Serial monitor").clicked() {}
+                    });
                 });
 
                 ui.with_layout(egui::Layout::top_down_justified(egui::Align::Min), |ui| {
                     ui.label("Controllers:");
-                    for controller in self.control_thread.get_controllers().iter() {
-                        if ui.button(&controller.name).clicked() {
-                            self.selected_controller = Some(controller.clone());
+                    let controllers = self.control_thread.controllers();
+                    let mut clicked = None;
+                    for (index, controller) in controllers.iter().enumerate() {
+                        let button = ui.button(&controller.name).on_hover_text(format!(
+                            "Firmware {}, protocol {}",
+                            controller.version.firmware_version,
+                            controller.version.protocol_version
+                        ));
+                        if button.clicked() {
                             log::info!("Initialized controller: {:?}", controller);
                             self.editor_view = EditorView::new(controller);
+                            clicked = Some(index);
+                        }
+                    }
+                    drop(controllers);
+                    if clicked.is_some() {
+                        self.selected_controller = clicked;
+                        if self.device_info_view.enabled {
+                            self.refresh_device_info();
                         }
                     }
                     if ui.button("Discover serial").clicked() {
                         self.control_thread.discover_controllers();
                     }
+                    if ui.button("Connect to address").clicked() {
+                        self.connection_view.enabled = true;
+                    }
                 });
             });
         egui::Window::new("Connection")
@@ -94,19 +184,97 @@ impl eframe::App for MyEguiApp {
             .show(ctx, |ui| {
                 self.connection_view.view.ui(ui);
             });
+        // closing the window while the code is asked for gives up on pairing
+        let connection_enabled = self.connection_view.enabled;
+        let connect_view = self
+            .connection_view
+            .as_original_mut::<ConnectionView>()
+            .unwrap();
+        if !connection_enabled && connect_view.pairing_with.take().is_some() {
+            self.control_thread.cancel_pairing();
+        }
+        egui::Window::new("Device info")
+            .resizable(false)
+            .default_width(300.0)
+            .open(&mut self.device_info_view.enabled)
+            .show(ctx, |ui| {
+                self.device_info_view.view.ui(ui);
+            });
 
         egui::CentralPanel::default().show(ctx, |ui| {
-            if let Some(mut controller) = self.selected_controller.clone() {
+            let mut controllers = self.control_thread.controllers();
+            // every controller, so switching back to one doesn't show what it was at probe time
+            let mut events = Vec::new();
+            for (index, controller) in controllers.iter_mut().enumerate() {
+                let polled = controller.poll_events();
+                if Some(index) == self.selected_controller {
+                    events = polled;
+                }
+            }
+            if !controllers.is_empty() {
+                // events arrive without any input, look for them regularly
+                ctx.request_repaint_after(Duration::from_millis(250));
+            }
+
+            let selected = self
+                .selected_controller
+                .and_then(|index| controllers.get_mut(index));
+            if let Some(controller) = selected {
+                for event in events {
+                    match event {
+                        Event::EffectChanged { .. } | Event::ParameterChanged { .. } => {
+                            self.editor_view.options = controller.options.clone();
+                            self.editor_view.selected_effect = controller.get_effect();
+                        }
+                        Event::Error(err) => {
+                            self.error_message =
+                                Some(format!("{} reported: {err}", controller.name));
+                        }
+                        _ => {}
+                    }
+                }
+                if let Some(mut power) = controller.power {
+                    ui.horizontal(|ui| {
+                        if ui.checkbox(&mut power.on, "Power").changed() {
+                            if let Err(err) = controller.set_power(power.on) {
+                                self.error_message = Some(format!("{err:#}"));
+                            }
+                        }
+                        let slider = egui::Slider::new(&mut power.brightness, 0.0..=1.0)
+                            .text("Brightness")
+                            .custom_formatter(|value, _| format!("{:.0}%", value * 100.0));
+                        let response = ui.add(slider);
+                        // the controller stores every value in flash, so only the one the drag
+                        // ends on is sent
+                        if response.drag_stopped() || (response.changed() && !response.dragged()) {
+                            if let Err(err) = controller.set_brightness(power.brightness) {
+                                self.error_message = Some(format!("{err:#}"));
+                            }
+                        } else if response.changed() {
+                            controller.power = Some(power);
+                        }
+                    });
+                    ui.separator();
+                }
+
                 self.editor_view.ui(ui);
                 if self.editor_view.changed_effect {
-                    controller.set_effect(&self.editor_view.selected_effect);
+                    if let Err(err) = controller.set_effect(&self.editor_view.selected_effect) {
+                        log::error!("Unable to set effect: {err:#}");
+                        self.error_message = Some(format!("Unable to set effect: {err:#}"));
+                    }
                     self.editor_view.options = controller.options.clone();
                     self.editor_view.changed_effect = false;
                 }
 
                 if self.editor_view.changed_option {
                     controller.options = self.editor_view.options.clone();
-                    controller.set_options();
+                    if let Err(err) = controller.set_options() {
+                        log::error!("{err:#}");
+                        self.error_message = Some(format!("{err:#}"));
+                    }
+                    // show values as clamped by the controller
+                    self.editor_view.options = controller.options.clone();
                     self.editor_view.changed_option = false;
                 }
             } else {
@@ -118,7 +286,32 @@ impl eframe::App for MyEguiApp {
             ui.label(format!("{}", self.control_thread.status()))
         });
 
-        match self.control_thread.status() {
+        if let Some(error_message) = &self.error_message {
+            if Message::new(error_message, views::message::DialogType::Ok).display(ctx) {
+                self.error_message = None;
+            }
+        }
+
+        let status = self.control_thread.status();
+        let connect_view = self
+            .connection_view
+            .as_original_mut::<ConnectionView>()
+            .unwrap();
+        (connect_view.pairing_with, connect_view.pairing_error) = match &status {
+            ChannelStatus::AwaitingPairingCode { address, error } => {
+                (Some(address.clone()), error.clone())
+            }
+            _ => (None, None),
+        };
+        match status {
+            ChannelStatus::AwaitingPairingCode { .. } => {
+                self.connection_view.enabled = true;
+            }
+            ChannelStatus::Failed(message) => {
+                log::error!("{message}");
+                self.error_message = Some(message);
+                self.control_thread.acknown_status();
+            }
             ChannelStatus::ProbingControllers(_) => {
                 Message::new(
                     "Probing controllers, please wait",
@@ -0,0 +1,84 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use protocol::Token;
+
+const FILE_NAME: &str = "espled-gui/tokens.json";
+
+/// Tokens issued to this app by paired controllers, by address. See [`protocol::pairing`]
+pub struct TokenStore {
+    /// `None` when there is no configuration directory, tokens then last until the app closes
+    path: Option<PathBuf>,
+    tokens: BTreeMap<String, Token>,
+}
+
+impl TokenStore {
+    /// Reads the tokens stored in the configuration directory of the user
+    pub fn load() -> Self {
+        let path = config_dir().map(|dir| dir.join(FILE_NAME));
+        let tokens = path
+            .as_deref()
+            .filter(|path| path.exists())
+            .map(|path| -> anyhow::Result<_> {
+                let text = fs::read_to_string(path)?;
+                Ok(serde_json::from_str(&text)?)
+            })
+            .transpose()
+            .unwrap_or_else(|err| {
+                log::warn!("cannot read paired controllers, pair them again: {err:#}");
+                None
+            })
+            .unwrap_or_default();
+        Self { path, tokens }
+    }
+
+    pub fn get(&self, address: &SocketAddr) -> Option<Token> {
+        self.tokens.get(&address.to_string()).copied()
+    }
+
+    pub fn insert(&mut self, address: &SocketAddr, token: Token) -> anyhow::Result<()> {
+        self.tokens.insert(address.to_string(), token);
+        self.save()
+    }
+
+    /// Forgets the token of a controller that no longer accepts it
+    pub fn remove(&mut self, address: &SocketAddr) -> anyhow::Result<()> {
+        self.tokens.remove(&address.to_string());
+        self.save()
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        // a token is all it takes to control the lights
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options
+            .open(path)
+            .with_context(|| format!("cannot store tokens in {}", path.display()))?;
+        file.write_all(serde_json::to_string_pretty(&self.tokens)?.as_bytes())?;
+        Ok(())
+    }
+}
+
+fn config_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    }
+}
@@ -1,5 +1,6 @@
 use eframe::egui;
-use std::net::{AddrParseError, Ipv4Addr};
+use protocol::pairing::CODE_DIGITS;
+use std::net::{AddrParseError, Ipv4Addr, SocketAddr};
 
 use super::View;
 
@@ -7,6 +8,12 @@ pub struct ConnectionView {
     ip_address: String,
     port: f32,
     pub connect_button_clicked: bool,
+    /// Address of the controller waiting for its pairing code, the code is asked for instead
+    pub pairing_with: Option<String>,
+    /// Why the previous code didn't work
+    pub pairing_error: Option<String>,
+    code: String,
+    pub pair_button_clicked: bool,
 }
 
 impl Default for ConnectionView {
@@ -15,6 +22,10 @@ impl Default for ConnectionView {
             port: 80.0,
             ip_address: String::default(),
             connect_button_clicked: false,
+            pairing_with: None,
+            pairing_error: None,
+            code: String::default(),
+            pair_button_clicked: false,
         }
     }
 }
@@ -31,11 +42,52 @@ impl ConnectionView {
     pub fn get_port(&self) -> u16 {
         self.port.clamp(0.0, 65536.0) as u16
     }
+
+    pub fn get_address(&self) -> Result<SocketAddr, AddrParseError> {
+        Ok(SocketAddr::from((self.get_ip_address()?, self.get_port())))
+    }
+
+    /// `None` unless the entered code has all its digits
+    pub fn get_code(&self) -> Option<u32> {
+        let code = self.code.trim();
+        if code.len() != CODE_DIGITS as usize || !code.bytes().all(|byte| byte.is_ascii_digit()) {
+            return None;
+        }
+        code.parse().ok()
+    }
+
+    /// Returns the entered code and clears it
+    pub fn take_code(&mut self) -> Option<u32> {
+        let code = self.get_code();
+        self.code.clear();
+        code
+    }
+
+    fn pairing_ui(&mut self, ui: &mut egui::Ui, address: &str) {
+        ui.label(format!(
+            "{address} needs to be paired. It blinks a code in white: every digit as that \
+             many flashes, ten for a zero, then a longer pause before it starts over."
+        ));
+        ui.label("Pairing code:");
+        ui.text_edit_singleline(&mut self.code);
+        if let Some(error) = &self.pairing_error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+        ui.add_enabled_ui(self.get_code().is_some(), |ui| {
+            self.pair_button_clicked = ui
+                .add(egui::Button::new("Pair").fill(egui::Color32::from_rgb(0, 0, 90)))
+                .clicked();
+        });
+    }
 }
 
 impl View for ConnectionView {
     fn ui(&mut self, ui: &mut egui::Ui) {
         ui.with_layout(egui::Layout::top_down_justified(egui::Align::Min), |ui| {
+            if let Some(address) = self.pairing_with.clone() {
+                self.pairing_ui(ui, &address);
+                return;
+            }
             ui.label("IP Address:");
             ui.text_edit_singleline(&mut self.ip_address);
             ui.label("Port:");
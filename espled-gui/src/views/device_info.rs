@@ -0,0 +1,94 @@
+use eframe::egui;
+use protocol::DeviceInfo;
+
+use super::View;
+
+#[derive(Default)]
+pub struct DeviceInfoView {
+    pub info: Option<DeviceInfo>,
+    pub refresh_clicked: bool,
+}
+
+fn format_uptime(uptime_ms: u64) -> String {
+    let seconds = uptime_ms / 1000;
+    let (days, hours, minutes, seconds) = (
+        seconds / 86_400,
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60,
+    );
+    if days > 0 {
+        format!("{days}d {hours:02}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{hours:02}:{minutes:02}:{seconds:02}")
+    }
+}
+
+fn format_kib(bytes: u32) -> String {
+    format!("{:.1} KiB", bytes as f32 / 1024.0)
+}
+
+impl View for DeviceInfoView {
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        let Some(info) = &self.info else {
+            ui.label("No information yet");
+            self.refresh_clicked = ui.button("Refresh").clicked();
+            return;
+        };
+
+        egui::Grid::new("device_info_grid")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                let mut row = |label: &str, value: String| {
+                    ui.label(label);
+                    ui.label(value);
+                    ui.end_row();
+                };
+                row("Firmware", info.firmware_version.clone());
+                row(
+                    "Commit",
+                    info.git_hash.clone().unwrap_or_else(|| "unknown".into()),
+                );
+                row("Built", info.build_date.clone());
+                row(
+                    "Chip",
+                    format!(
+                        "{} rev {}.{}",
+                        info.chip_model,
+                        info.chip_revision / 100,
+                        info.chip_revision % 100
+                    ),
+                );
+                row("MAC address", info.mac_address.to_string());
+                row("Uptime", format_uptime(info.uptime_ms));
+                row(
+                    "Free heap",
+                    format!(
+                        "{} (lowest {})",
+                        format_kib(info.free_heap),
+                        format_kib(info.min_free_heap)
+                    ),
+                );
+                row(
+                    "NVS entries",
+                    info.nvs.map_or_else(
+                        || "unknown".into(),
+                        |nvs| format!("{} of {} used", nvs.used_entries, nvs.total_entries),
+                    ),
+                );
+                row("Last reset", info.reset_reason.to_string());
+            });
+
+        ui.separator();
+        self.refresh_clicked = ui.button("Refresh").clicked();
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+}
@@ -1,15 +1,17 @@
-use std::{collections::HashMap, env::consts};
-
 use eframe::egui::{self, widgets};
-use protocol::{ParameterTypes, RGBLedColor};
+use protocol::{
+    color, ColorStop, EffectInfo, Interpolation, Palette, ParameterDescriptor, ParameterTypes,
+    RGBLedColor,
+};
 
 use crate::control_thread::Controller;
 
 use super::View;
 #[derive(Default)]
 pub struct EditorView {
-    pub options: HashMap<String, ParameterTypes>,
-    pub effects: Vec<String>,
+    pub options: Vec<ParameterDescriptor>,
+    pub effects: Vec<EffectInfo>,
+    /// [`EffectInfo::id`]
     pub selected_effect: String,
     pub changed_option: bool,
     pub changed_effect: bool,
@@ -20,7 +22,7 @@ impl EditorView {
     pub fn new(controller: &Controller) -> Self {
         Self {
             options: controller.options.clone(),
-            effects: controller.effect_list.clone(),
+            effects: controller.effects.clone(),
             changed_option: false,
             changed_effect: false,
             selected_effect: controller.get_effect(),
@@ -28,18 +30,172 @@ impl EditorView {
     }
 }
 
+fn parameter_widget(ui: &mut egui::Ui, descriptor: &mut ParameterDescriptor) {
+    let suffix = descriptor
+        .unit
+        .as_ref()
+        .map(|unit| format!(" {unit}"))
+        .unwrap_or_default();
+
+    match &mut descriptor.value {
+        ParameterTypes::Color(rgbled_color) => {
+            let mut color_array: [f32; 3] = (*rgbled_color).into();
+            widgets::color_picker::color_edit_button_rgb(ui, &mut color_array);
+            *rgbled_color = RGBLedColor::from(color_array);
+        }
+        ParameterTypes::Float(value) => match (descriptor.min, descriptor.max) {
+            (Some(min), Some(max)) => {
+                let mut slider = egui::Slider::new(value, min..=max).suffix(suffix);
+                if let Some(step) = descriptor.step {
+                    slider = slider.step_by(step as f64);
+                }
+                ui.add(slider);
+            }
+            (min, max) => {
+                let range = min.unwrap_or(f32::MIN)..=max.unwrap_or(f32::MAX);
+                let speed = descriptor.step.unwrap_or(0.1);
+                ui.add(
+                    egui::DragValue::new(value)
+                        .range(range)
+                        .speed(speed)
+                        .suffix(suffix),
+                );
+            }
+        },
+        ParameterTypes::Int(value) => {
+            let min = descriptor.min.map_or(i32::MIN, |min| min.ceil() as i32);
+            let max = descriptor.max.map_or(i32::MAX, |max| max.floor() as i32);
+            let speed = descriptor.step.unwrap_or(1.0);
+            ui.add(
+                egui::DragValue::new(value)
+                    .range(min..=max)
+                    .speed(speed)
+                    .suffix(suffix),
+            );
+        }
+        ParameterTypes::Bool(value) => {
+            ui.checkbox(value, "");
+        }
+        ParameterTypes::Choice { selected, options } => {
+            egui::ComboBox::from_id_salt(descriptor.key.as_str()).show_index(
+                ui,
+                selected,
+                options.len(),
+                |index| options.get(index).cloned().unwrap_or_default(),
+            );
+        }
+        ParameterTypes::Text(value) => {
+            ui.text_edit_singleline(value);
+        }
+        ParameterTypes::Palette(palette) => {
+            palette_editor(ui, &descriptor.key, palette);
+        }
+        ParameterTypes::Kelvin(value) => {
+            let min = descriptor.min.map_or(color::MIN_KELVIN, |min| min.ceil() as u16);
+            let max = descriptor.max.map_or(color::MAX_KELVIN, |max| max.floor() as u16);
+            let mut slider = egui::Slider::new(value, min..=max)
+                .logarithmic(true)
+                .suffix(suffix);
+            if let Some(step) = descriptor.step {
+                slider = slider.step_by(step as f64);
+            }
+            ui.add(slider);
+
+            let swatch = RGBLedColor::from_kelvin(*value);
+            let (rect, _) = ui.allocate_exact_size(egui::vec2(16.0, 16.0), egui::Sense::hover());
+            ui.painter().rect_filled(
+                rect,
+                2.0,
+                egui::Color32::from_rgb(swatch.red, swatch.green, swatch.blue),
+            );
+        }
+    }
+}
+
+fn palette_editor(ui: &mut egui::Ui, id: &str, palette: &mut Palette) {
+    const PREVIEW_STEPS: usize = 64;
+
+    ui.vertical(|ui| {
+        // the controller keeps stops sorted, sample a sorted copy so rows don't jump while dragging
+        let sorted = Palette::new(palette.stops.clone(), palette.interpolation);
+        let (rect, _) = ui.allocate_exact_size(
+            egui::vec2(ui.available_width().min(240.0), 16.0),
+            egui::Sense::hover(),
+        );
+        let step_width = rect.width() / PREVIEW_STEPS as f32;
+        for step in 0..PREVIEW_STEPS {
+            let color = sorted.sample(step as f32 / (PREVIEW_STEPS - 1) as f32);
+            let min = egui::pos2(rect.left() + step as f32 * step_width, rect.top());
+            ui.painter().rect_filled(
+                egui::Rect::from_min_size(min, egui::vec2(step_width, rect.height())),
+                0.0,
+                egui::Color32::from_rgb(color.red, color.green, color.blue),
+            );
+        }
+
+        let mut removed = None;
+        for (index, stop) in palette.stops.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                let mut color_array: [f32; 3] = stop.color.into();
+                widgets::color_picker::color_edit_button_rgb(ui, &mut color_array);
+                stop.color = RGBLedColor::from(color_array);
+                ui.add(egui::Slider::new(&mut stop.position, 0.0..=1.0));
+                if ui.small_button("🗑").on_hover_text("Remove stop").clicked() {
+                    removed = Some(index);
+                }
+            });
+        }
+        if let Some(index) = removed {
+            palette.stops.remove(index);
+        }
+
+        ui.horizontal(|ui| {
+            ui.add_enabled_ui(palette.stops.len() < Palette::MAX_STOPS, |ui| {
+                if ui.button("Add stop").clicked() {
+                    let position = sorted
+                        .stops
+                        .last()
+                        .map_or(0.5, |last| (last.position + 1.0) / 2.0);
+                    palette
+                        .stops
+                        .push(ColorStop::new(position, sorted.sample(position)));
+                }
+            });
+            egui::ComboBox::from_id_salt((id, "interpolation"))
+                .selected_text(format!("{:?}", palette.interpolation))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(
+                        &mut palette.interpolation,
+                        Interpolation::Linear,
+                        "Linear",
+                    );
+                    ui.selectable_value(
+                        &mut palette.interpolation,
+                        Interpolation::Stepped,
+                        "Stepped",
+                    );
+                });
+        });
+    });
+}
+
 impl View for EditorView {
     fn ui(&mut self, ui: &mut egui::Ui) {
         ui.with_layout(egui::Layout::top_down_justified(egui::Align::Min), |ui| {
             ui.horizontal(|ui| {
                 ui.label("Effect");
+                let selected_name = self
+                    .effects
+                    .iter()
+                    .find(|effect| effect.id == self.selected_effect)
+                    .map_or(self.selected_effect.as_str(), |effect| effect.name.as_str());
                 egui::ComboBox::from_id_salt(41951919)
-                .selected_text(format!("{:?}", self.selected_effect))
+                .selected_text(format!("{:?}", selected_name))
                 .show_ui(ui, |ui| {
 
                     let mut new_effect = self.selected_effect.clone();
                     for effect in self.effects.iter() {
-                        ui.selectable_value(&mut new_effect, effect.clone(), effect);
+                        ui.selectable_value(&mut new_effect, effect.id.clone(), &effect.name);
                     }
                     if new_effect != self.selected_effect {
                         self.selected_effect = new_effect;
@@ -48,25 +204,25 @@ impl View for EditorView {
                 }
                 );
             });
-            for (key, value) in self.options.iter_mut() {
+            let mut group = None;
+            for descriptor in self.options.iter_mut() {
+                if descriptor.group != group {
+                    group = descriptor.group.clone();
+                    if let Some(group) = &group {
+                        ui.separator();
+                        ui.strong(group);
+                    }
+                }
                 ui.horizontal(|ui| {
-                    ui.label(key);
-                    match value {
-                        ParameterTypes::Color(rgbled_color) => {
-                            let mut color_array: [f32; 3] = (*rgbled_color).into();
-                            widgets::color_picker::color_edit_button_rgb(ui, &mut color_array);
-                            let new_color = RGBLedColor::from(color_array);
-                            if new_color != *rgbled_color {
-                                *rgbled_color = new_color;
-                                //self.changed_option = true;
-                            }
-                        },
-                        ParameterTypes::Float(value) => {
-                            let new_value = value.clone();
-                            if ui.add(egui::Slider::new(value, 0.0..=100.0)).changed() {
-                                //self.changed_option = true;
-                            }
-                        },
+                    ui.label(&descriptor.label).on_hover_text(&descriptor.key);
+                    parameter_widget(ui, descriptor);
+                    if descriptor.value != descriptor.default
+                        && ui
+                            .small_button("⟲")
+                            .on_hover_text("Reset to default")
+                            .clicked()
+                    {
+                        descriptor.value = descriptor.default.clone();
                     }
                 });
             }
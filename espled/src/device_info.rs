@@ -0,0 +1,79 @@
+use esp_idf_svc::sys::{self, esp};
+use protocol::{DeviceInfo, MacAddress, NvsUsage, ResetReason};
+
+use crate::FIRMWARE_VERSION;
+
+/// Snapshot of the build, the chip and its health for [`protocol::Request::GetDeviceInfo`]
+pub fn device_info() -> DeviceInfo {
+    let mut chip = sys::esp_chip_info_t::default();
+    unsafe { sys::esp_chip_info(&mut chip) };
+
+    DeviceInfo {
+        firmware_version: FIRMWARE_VERSION.to_string(),
+        git_hash: option_env!("ESPLED_GIT_HASH").map(str::to_string),
+        build_date: env!("ESPLED_BUILD_DATE").to_string(),
+        chip_model: chip_model(chip.model),
+        chip_revision: chip.revision,
+        mac_address: mac_address(),
+        uptime_ms: (unsafe { sys::esp_timer_get_time() } / 1000) as u64,
+        free_heap: unsafe { sys::esp_get_free_heap_size() },
+        min_free_heap: unsafe { sys::esp_get_minimum_free_heap_size() },
+        nvs: nvs_usage()
+            .inspect_err(|err| log::warn!("cannot read NVS statistics: {err}"))
+            .ok(),
+        reset_reason: reset_reason(),
+    }
+}
+
+fn chip_model(model: sys::esp_chip_model_t) -> String {
+    match model {
+        sys::esp_chip_model_t_CHIP_ESP32 => "ESP32".to_string(),
+        sys::esp_chip_model_t_CHIP_ESP32S2 => "ESP32-S2".to_string(),
+        sys::esp_chip_model_t_CHIP_ESP32S3 => "ESP32-S3".to_string(),
+        sys::esp_chip_model_t_CHIP_ESP32C3 => "ESP32-C3".to_string(),
+        sys::esp_chip_model_t_CHIP_ESP32C2 => "ESP32-C2".to_string(),
+        sys::esp_chip_model_t_CHIP_ESP32C6 => "ESP32-C6".to_string(),
+        sys::esp_chip_model_t_CHIP_ESP32H2 => "ESP32-H2".to_string(),
+        model => format!("unknown chip {model}"),
+    }
+}
+
+fn mac_address() -> MacAddress {
+    let mut mac = [0u8; 6];
+    // the factory MAC is burnt into eFuse, reading it only fails on a damaged chip
+    if let Err(err) = esp!(unsafe { sys::esp_efuse_mac_get_default(mac.as_mut_ptr()) }) {
+        log::warn!("cannot read MAC address: {err}");
+    }
+    MacAddress(mac)
+}
+
+fn nvs_usage() -> Result<NvsUsage, sys::EspError> {
+    let mut stats = sys::nvs_stats_t::default();
+    // a null partition name is the default NVS partition
+    esp!(unsafe { sys::nvs_get_stats(std::ptr::null(), &mut stats) })?;
+    Ok(NvsUsage {
+        used_entries: stats.used_entries as u32,
+        total_entries: stats.total_entries as u32,
+    })
+}
+
+fn reset_reason() -> ResetReason {
+    match unsafe { sys::esp_reset_reason() } {
+        sys::esp_reset_reason_t_ESP_RST_POWERON => ResetReason::PowerOn,
+        sys::esp_reset_reason_t_ESP_RST_EXT => ResetReason::External,
+        sys::esp_reset_reason_t_ESP_RST_SW => ResetReason::Software,
+        sys::esp_reset_reason_t_ESP_RST_PANIC => ResetReason::Panic,
+        sys::esp_reset_reason_t_ESP_RST_INT_WDT => ResetReason::InterruptWatchdog,
+        sys::esp_reset_reason_t_ESP_RST_TASK_WDT => ResetReason::TaskWatchdog,
+        sys::esp_reset_reason_t_ESP_RST_WDT => ResetReason::Watchdog,
+        sys::esp_reset_reason_t_ESP_RST_DEEPSLEEP => ResetReason::DeepSleep,
+        sys::esp_reset_reason_t_ESP_RST_BROWNOUT => ResetReason::Brownout,
+        sys::esp_reset_reason_t_ESP_RST_SDIO => ResetReason::Sdio,
+        sys::esp_reset_reason_t_ESP_RST_USB => ResetReason::Usb,
+        sys::esp_reset_reason_t_ESP_RST_JTAG => ResetReason::Jtag,
+        sys::esp_reset_reason_t_ESP_RST_EFUSE => ResetReason::EfuseError,
+        sys::esp_reset_reason_t_ESP_RST_PWR_GLITCH => ResetReason::PowerGlitch,
+        sys::esp_reset_reason_t_ESP_RST_CPU_LOCKUP => ResetReason::CpuLockup,
+        _ => ResetReason::Unknown,
+    }
+}
@@ -1,36 +1,37 @@
-use protocol::RGBLedColor;
+use protocol::{ParameterDescriptor, RGBLedColor};
 
-use super::{Effect, ParameterTypes};
-use std::collections::HashMap;
+use super::{find_parameter, update_parameter, Effect, ParameterTypes};
 
 pub struct Decay {
-    parameters: HashMap<String, super::ParameterTypes>,
+    parameters: Vec<ParameterDescriptor>,
     color: f32,
 }
 
 impl Decay {
     pub fn new() -> Self {
         Self {
-            parameters: HashMap::from([
-                (String::from("speed"), ParameterTypes::Float(1.0)),
-            ]),
+            parameters: vec![
+                ParameterDescriptor::new("speed", "Speed", ParameterTypes::Float(1.0))
+                    .range(0.0, 10.0)
+                    .step(0.1)
+                    .unit("Hz"),
+            ],
             color: 0.0,
         }
     }
 }
 
 impl Effect for Decay {
-    fn get_parameters(&self) -> HashMap<String, ParameterTypes> {
+    fn get_parameters(&self) -> Vec<ParameterDescriptor> {
         self.parameters.clone()
     }
 
     fn set_parameter(&mut self, parameter: &str, value: ParameterTypes) -> bool {
-        if self.parameters.get(parameter).is_none() {
-            return false;
-        }
+        update_parameter(&mut self.parameters, parameter, value)
+    }
 
-        self.parameters.insert(parameter.to_string(), value);
-        true
+    fn id(&self) -> &'static str {
+        "decay"
     }
 
     fn name(&self) -> &str {
@@ -38,7 +39,9 @@ impl Effect for Decay {
     }
 
     fn update(&mut self, delta_time: f32) {
-        let speed = self.parameters.get("speed").unwrap().as_f32().unwrap();
+        let speed = find_parameter(&self.parameters, "speed")
+            .and_then(ParameterTypes::as_f32)
+            .unwrap();
         
         if self.color < 1.0 {
             self.color += speed * delta_time;
@@ -1,25 +1,25 @@
-use protocol::RGBLedColor;
+use protocol::{ParameterDescriptor, RGBLedColor};
 
-use super::{Effect, ParameterTypes};
-use std::collections::HashMap;
+use super::{find_parameter, update_parameter, Effect, ParameterTypes};
 
 #[derive(Default)]
 pub struct Direct {
-    parameters: HashMap<String, super::ParameterTypes>,
+    parameters: Vec<ParameterDescriptor>,
 }
 
 impl Direct {
     pub fn new() -> Self {
         Self {
-            parameters: HashMap::from([(
-                String::from("color"),
+            parameters: vec![ParameterDescriptor::new(
+                "color",
+                "Color",
                 ParameterTypes::Color(RGBLedColor::default()),
-            )]),
+            )],
         }
     }
 
     fn get_color(&self) -> RGBLedColor {
-        match self.parameters.get("color") {
+        match find_parameter(&self.parameters, "color") {
             Some(ParameterTypes::Color(color)) => *color,
             _ => RGBLedColor::default(),
         }
@@ -27,17 +27,16 @@ impl Direct {
 }
 
 impl Effect for Direct {
-    fn get_parameters(&self) -> HashMap<String, ParameterTypes> {
+    fn get_parameters(&self) -> Vec<ParameterDescriptor> {
         self.parameters.clone()
     }
 
     fn set_parameter(&mut self, parameter: &str, value: ParameterTypes) -> bool {
-        if self.parameters.get(parameter).is_none() {
-            return false;
-        }
+        update_parameter(&mut self.parameters, parameter, value)
+    }
 
-        self.parameters.insert(parameter.to_string(), value);
-        true
+    fn id(&self) -> &'static str {
+        "direct"
     }
 
     fn name(&self) -> &str {
@@ -1,38 +1,52 @@
-use protocol::RGBLedColor;
+use protocol::{color::normalize_hue, ParameterDescriptor, RGBLedColor};
 
-use super::{Effect, ParameterTypes};
-use std::collections::HashMap;
+use super::{find_parameter, update_parameter, Effect, ParameterTypes};
 
 pub struct HueRotate {
-    parameters: HashMap<String, super::ParameterTypes>,
+    parameters: Vec<ParameterDescriptor>,
     hue: f32,
 }
 
 impl HueRotate {
     pub fn new() -> Self {
         Self {
-            parameters: HashMap::from([
-                (String::from("saturation"), ParameterTypes::Float(1.0)),
-                (String::from("value"), ParameterTypes::Float(1.0)),
-                (String::from("speed"), ParameterTypes::Float(1.0)),
-            ]),
+            parameters: vec![
+                ParameterDescriptor::new("saturation", "Saturation", ParameterTypes::Float(1.0))
+                    .range(0.0, 1.0)
+                    .step(0.01)
+                    .group("Color"),
+                ParameterDescriptor::new("value", "Value", ParameterTypes::Float(1.0))
+                    .range(0.0, 1.0)
+                    .step(0.01)
+                    .group("Color"),
+                ParameterDescriptor::new("speed", "Speed", ParameterTypes::Float(1.0))
+                    .range(0.0, 360.0)
+                    .step(1.0)
+                    .unit("°/s")
+                    .group("Animation"),
+            ],
             hue: 0.0,
         }
     }
+
+    fn get_f32(&self, key: &str) -> f32 {
+        find_parameter(&self.parameters, key)
+            .and_then(ParameterTypes::as_f32)
+            .unwrap()
+    }
 }
 
 impl Effect for HueRotate {
-    fn get_parameters(&self) -> HashMap<String, ParameterTypes> {
+    fn get_parameters(&self) -> Vec<ParameterDescriptor> {
         self.parameters.clone()
     }
 
     fn set_parameter(&mut self, parameter: &str, value: ParameterTypes) -> bool {
-        if self.parameters.get(parameter).is_none() {
-            return false;
-        }
+        update_parameter(&mut self.parameters, parameter, value)
+    }
 
-        self.parameters.insert(parameter.to_string(), value);
-        true
+    fn id(&self) -> &'static str {
+        "hue_rotate"
     }
 
     fn name(&self) -> &str {
@@ -40,17 +54,13 @@ impl Effect for HueRotate {
     }
 
     fn update(&mut self, delta_time: f32) {
-        let speed = self.parameters.get("speed").unwrap().as_f32().unwrap();
-        if self.hue >= 360.0 {
-            self.hue = 0.0;
-        } else {
-            self.hue += speed * delta_time;
-        }
+        let speed = self.get_f32("speed");
+        self.hue = normalize_hue(self.hue + speed * delta_time);
     }
 
     fn render(&self) -> RGBLedColor {
-        let saturation = self.parameters.get("saturation").unwrap().as_f32().unwrap();
-        let value = self.parameters.get("value").unwrap().as_f32().unwrap();
+        let saturation = self.get_f32("saturation");
+        let value = self.get_f32("value");
         RGBLedColor::from_hsv(self.hue, saturation, value)
     }
 }
@@ -1,55 +1,143 @@
-use std::collections::HashMap;
-
 use esp_idf_svc::nvs::{EspNvs, EspNvsPartition, NvsDefault};
-use protocol::{ParameterTypes, RGBLedColor};
+use protocol::{Palette, ParameterDescriptor, ParameterTypes, RGBLedColor};
 
 pub mod direct;
 pub mod huerotate;
 pub mod decay;
+pub mod palette;
+pub mod temperature;
+
+/// Longest [`ParameterTypes::Text`] value that fits the NVS read buffer
+pub const MAX_TEXT_LENGTH: usize = 127;
+
+/// Longest [`Effect::id`]. It names the NVS namespace of the effect's parameters, which ESP-IDF
+/// limits to 15 bytes
+pub const MAX_ID_LENGTH: usize = 15;
+
+/// Current value of `key` in an effect's parameter list
+pub fn find_parameter<'a>(
+    parameters: &'a [ParameterDescriptor],
+    key: &str,
+) -> Option<&'a ParameterTypes> {
+    parameters
+        .iter()
+        .find(|descriptor| descriptor.key == key)
+        .map(|descriptor| &descriptor.value)
+}
+
+/// Replaces the value of `key`, returns `false` if there is no such parameter
+pub fn update_parameter(
+    parameters: &mut [ParameterDescriptor],
+    key: &str,
+    value: ParameterTypes,
+) -> bool {
+    match parameters.iter_mut().find(|descriptor| descriptor.key == key) {
+        Some(descriptor) => {
+            descriptor.value = value;
+            true
+        }
+        None => false,
+    }
+}
 
 pub trait Effect {
-    fn get_parameters(&self) -> HashMap<String, ParameterTypes>;
+    /// Parameters in the order they are shown to the user
+    fn get_parameters(&self) -> Vec<ParameterDescriptor>;
     fn set_parameter(&mut self, parameter_name: &str, value: ParameterTypes) -> bool;
+    /// Stable identifier, stored in NVS and used by clients. Never change it once released,
+    /// unlike [`Effect::name`] and the position in the effect list. The parameters are stored in
+    /// the NVS namespace of this name, see [`MAX_ID_LENGTH`]
+    fn id(&self) -> &'static str;
     fn name(&self) -> &str;
-    fn init(&mut self, nvs_partition: EspNvsPartition<NvsDefault>) {
-        let nvs = EspNvs::new(nvs_partition.clone(), self.name(), true).unwrap();
-
-        for (key, value) in self.get_parameters() {
-            match value {
-                ParameterTypes::Color(_) => {
-                    if let Ok(color_u32) = nvs.get_u32(&key) {
-                        self.set_parameter(
-                            &key,
-                            ParameterTypes::Color(RGBLedColor::new_from_u32(
-                                color_u32.unwrap_or(0xffffff),
-                            )),
-                        );
-                    }
-                }
-                ParameterTypes::Float(_) => {
-                    if let Ok(float_bits) = nvs.get_u32(&key) {
-                        self.set_parameter(
-                            &key,
-                            ParameterTypes::Float(f32::from_bits(float_bits.unwrap_or(0))),
-                        );
-                    }
+    /// Reads the stored parameters, the ones that can't be read keep their current value
+    fn init(&mut self, nvs_partition: EspNvsPartition<NvsDefault>) -> anyhow::Result<()> {
+        let nvs = EspNvs::new(nvs_partition.clone(), self.id(), true)?;
+        self.load(&nvs);
+        Ok(())
+    }
+    /// Reads the parameters stored in `nvs`, see [`Effect::init`]
+    /// Stored values are clamped like requested ones, the declared range may have shrunk since
+    /// they were saved
+    fn load(&mut self, nvs: &EspNvs<NvsDefault>) {
+        for descriptor in self.get_parameters() {
+            let key = &descriptor.key;
+            let stored = match &descriptor.value {
+                ParameterTypes::Color(_) => nvs.get_u32(key).ok().map(|color_u32| {
+                    ParameterTypes::Color(RGBLedColor::new_from_u32(color_u32.unwrap_or(0xffffff)))
+                }),
+                ParameterTypes::Float(_) => nvs
+                    .get_u32(key)
+                    .ok()
+                    .map(|float_bits| ParameterTypes::Float(f32::from_bits(float_bits.unwrap_or(0)))),
+                ParameterTypes::Int(_) => nvs.get_i32(key).ok().flatten().map(ParameterTypes::Int),
+                ParameterTypes::Bool(_) => nvs
+                    .get_u8(key)
+                    .ok()
+                    .flatten()
+                    .map(|value| ParameterTypes::Bool(value != 0)),
+                ParameterTypes::Choice { options, .. } => nvs
+                    .get_u32(key)
+                    .ok()
+                    .flatten()
+                    .filter(|&selected| (selected as usize) < options.len())
+                    .map(|selected| ParameterTypes::Choice {
+                        selected: selected as usize,
+                        options: options.clone(),
+                    }),
+                ParameterTypes::Text(_) => {
+                    let mut buffer = [0; MAX_TEXT_LENGTH + 1];
+                    nvs.get_str(key, &mut buffer)
+                        .ok()
+                        .flatten()
+                        .map(|text| ParameterTypes::Text(text.to_string()))
+                }
+                ParameterTypes::Palette(_) => {
+                    let mut buffer = [0; Palette::max_bytes()];
+                    nvs.get_blob(key, &mut buffer)
+                        .ok()
+                        .flatten()
+                        .and_then(Palette::from_bytes)
+                        .map(ParameterTypes::Palette)
                 }
+                ParameterTypes::Kelvin(_) => nvs.get_u16(key).ok().flatten().map(ParameterTypes::Kelvin),
+            };
+            if let Some(value) = stored {
+                self.set_parameter(key, descriptor.clamp(value));
             }
         }
     }
-    fn save(&mut self, nvs_partition: EspNvsPartition<NvsDefault>) {
+    fn save(&mut self, nvs_partition: EspNvsPartition<NvsDefault>) -> anyhow::Result<()> {
         let parameters = self.get_parameters();
-        let nvs = EspNvs::new(nvs_partition.clone(), self.name(), true).unwrap();
-        for (key, value) in parameters {
+        let nvs = EspNvs::new(nvs_partition.clone(), self.id(), true)?;
+        for ParameterDescriptor { key, value, .. } in parameters {
             match value {
                 ParameterTypes::Color(rgbled_color) => {
-                    nvs.set_u32(&key, rgbled_color.to_u32()).unwrap();
+                    nvs.set_u32(&key, rgbled_color.to_u32())?;
                 }
                 ParameterTypes::Float(value) => {
-                    nvs.set_u32(&key, value.to_bits()).unwrap();
+                    nvs.set_u32(&key, value.to_bits())?;
+                }
+                ParameterTypes::Int(value) => {
+                    nvs.set_i32(&key, value)?;
+                }
+                ParameterTypes::Bool(value) => {
+                    nvs.set_u8(&key, value as u8)?;
+                }
+                ParameterTypes::Choice { selected, .. } => {
+                    nvs.set_u32(&key, selected as u32)?;
+                }
+                ParameterTypes::Text(text) => {
+                    nvs.set_str(&key, &text)?;
+                }
+                ParameterTypes::Palette(palette) => {
+                    nvs.set_blob(&key, &palette.to_bytes())?;
+                }
+                ParameterTypes::Kelvin(value) => {
+                    nvs.set_u16(&key, value)?;
                 }
             }
         }
+        Ok(())
     }
     fn update(&mut self, delta_time: f32);
     fn render(&self) -> RGBLedColor;
@@ -0,0 +1,68 @@
+use protocol::{ColorStop, Interpolation, Palette, ParameterDescriptor, RGBLedColor};
+
+use super::{find_parameter, update_parameter, Effect, ParameterTypes};
+
+/// Moves through a user defined palette and back
+pub struct PaletteCycle {
+    parameters: Vec<ParameterDescriptor>,
+    position: f32,
+}
+
+impl PaletteCycle {
+    pub fn new() -> Self {
+        let palette = Palette::new(
+            vec![
+                ColorStop::new(0.0, RGBLedColor::new(255, 0, 0)),
+                ColorStop::new(0.5, RGBLedColor::new(0, 255, 0)),
+                ColorStop::new(1.0, RGBLedColor::new(0, 0, 255)),
+            ],
+            Interpolation::Linear,
+        );
+
+        Self {
+            parameters: vec![
+                ParameterDescriptor::new("palette", "Palette", ParameterTypes::Palette(palette)),
+                ParameterDescriptor::new("speed", "Speed", ParameterTypes::Float(0.1))
+                    .range(0.0, 10.0)
+                    .step(0.01)
+                    .unit("Hz"),
+            ],
+            position: 0.0,
+        }
+    }
+}
+
+impl Effect for PaletteCycle {
+    fn get_parameters(&self) -> Vec<ParameterDescriptor> {
+        self.parameters.clone()
+    }
+
+    fn set_parameter(&mut self, parameter: &str, value: ParameterTypes) -> bool {
+        update_parameter(&mut self.parameters, parameter, value)
+    }
+
+    fn id(&self) -> &'static str {
+        "palette"
+    }
+
+    fn name(&self) -> &str {
+        "Palette"
+    }
+
+    fn update(&mut self, delta_time: f32) {
+        let speed = find_parameter(&self.parameters, "speed")
+            .and_then(ParameterTypes::as_f32)
+            .unwrap();
+
+        // one period goes to the end of the palette and back, so there is no jump at the wrap
+        self.position = (self.position + speed * delta_time).rem_euclid(1.0);
+    }
+
+    fn render(&self) -> RGBLedColor {
+        let t = 1.0 - (self.position * 2.0 - 1.0).abs();
+        find_parameter(&self.parameters, "palette")
+            .and_then(ParameterTypes::as_palette)
+            .map(|palette| palette.sample(t))
+            .unwrap_or_default()
+    }
+}
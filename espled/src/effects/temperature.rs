@@ -0,0 +1,54 @@
+use protocol::{color, ParameterDescriptor, RGBLedColor};
+
+use super::{find_parameter, update_parameter, Effect, ParameterTypes};
+
+/// White light of a given colour temperature, for room lighting
+pub struct Temperature {
+    parameters: Vec<ParameterDescriptor>,
+}
+
+impl Temperature {
+    pub fn new() -> Self {
+        Self {
+            parameters: vec![
+                ParameterDescriptor::new("temperature", "Temperature", ParameterTypes::Kelvin(2700))
+                    .range(color::MIN_KELVIN as f32, color::MAX_KELVIN as f32)
+                    .step(100.0)
+                    .unit("K"),
+                ParameterDescriptor::new("brightness", "Brightness", ParameterTypes::Float(1.0))
+                    .range(0.0, 1.0)
+                    .step(0.01),
+            ],
+        }
+    }
+}
+
+impl Effect for Temperature {
+    fn get_parameters(&self) -> Vec<ParameterDescriptor> {
+        self.parameters.clone()
+    }
+
+    fn set_parameter(&mut self, parameter: &str, value: ParameterTypes) -> bool {
+        update_parameter(&mut self.parameters, parameter, value)
+    }
+
+    fn id(&self) -> &'static str {
+        "temperature"
+    }
+
+    fn name(&self) -> &str {
+        "Color Temperature"
+    }
+
+    fn update(&mut self, _delta_time: f32) {}
+
+    fn render(&self) -> RGBLedColor {
+        let kelvin = find_parameter(&self.parameters, "temperature")
+            .and_then(ParameterTypes::as_kelvin)
+            .unwrap();
+        let brightness = find_parameter(&self.parameters, "brightness")
+            .and_then(ParameterTypes::as_f32)
+            .unwrap();
+        RGBLedColor::from_kelvin(kelvin).scale(brightness)
+    }
+}
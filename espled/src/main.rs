@@ -1,25 +1,65 @@
-#![feature(try_blocks)]
-#![feature(buf_read_has_data_left)]
-
 pub mod serial_configuration;
-use std::io::BufRead;
+use std::io::{BufRead, Write};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use esp_idf_hal::delay::FreeRtos;
 use esp_idf_hal::ledc::config::TimerConfig;
 use esp_idf_hal::ledc::{LedcDriver, LedcTimerDriver};
 use esp_idf_hal::prelude::*;
 use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, EspNvsPartition, NvsDefault};
-use protocol::Request;
+use protocol::command::{self, Command};
+use protocol::framing::{self, Encoding, FrameDecoder};
+use protocol::pairing::CODE_TIMEOUT_MS;
+use protocol::{
+    Capability, ErrorCode, Event, EventKind, Request, RequestFrame, RequestHeader, Response,
+    VersionInfo, ZoneInfo, DEFAULT_ZONE, PROTOCOL_VERSION,
+};
+use serde::Serialize;
 
-use crate::rgbcontrol::RgbControl;
+use crate::pairing::Pairing;
+use crate::rgbcontrol::{RgbControl, WhiteOutput};
 
+pub mod device_info;
 pub mod effects;
+pub mod pairing;
 pub mod rgb;
 pub mod rgbcontrol;
 //pub mod server;
 
 const NAME: &str = "LentO'Chka";
+const FIRMWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Name of [`DEFAULT_ZONE`], the only output
+const ZONE_NAME: &str = "LED strip";
+/// Rated colour temperature of the white dies, used to extract white from the rendered colour
+#[cfg(all(feature = "rgbw", not(feature = "rgbww")))]
+const WHITE_KELVIN: u16 = 4000;
+#[cfg(feature = "rgbww")]
+const WARM_WHITE_KELVIN: u16 = 2700;
+#[cfg(feature = "rgbww")]
+const COLD_WHITE_KELVIN: u16 = 6500;
+const CAPABILITIES: &[Capability] = &[
+    Capability::ColorParameter,
+    Capability::FloatParameter,
+    Capability::SerialTransport,
+    Capability::BinaryFraming,
+    Capability::FrameChecksum,
+    Capability::IntParameter,
+    Capability::BoolParameter,
+    Capability::ChoiceParameter,
+    Capability::TextParameter,
+    Capability::PaletteParameter,
+    Capability::KelvinParameter,
+    Capability::ColorStrings,
+    Capability::BatchParameters,
+    Capability::Events,
+    Capability::ColorStreaming,
+    Capability::DeviceInfo,
+    Capability::PowerControl,
+    Capability::EffectIds,
+    Capability::Zones,
+    Capability::Pairing,
+];
 
 fn nvs_get_string(key: &str, nvs: EspNvsPartition<NvsDefault>) -> String {
     let mut buffer: [u8; 128] = [0; 128];
@@ -28,6 +68,200 @@ fn nvs_get_string(key: &str, nvs: EspNvsPartition<NvsDefault>) -> String {
     return stro4ka.unwrap_or_default().to_string();
 }
 
+fn respond<T: Serialize>(encoding: Encoding, id: Option<u32>, result: Result<T, protocol::Error>) {
+    let frame = framing::encode(encoding, &Response::new(id, result));
+    let mut stdout = std::io::stdout().lock();
+    let _ = stdout.write_all(&frame);
+    let _ = stdout.flush();
+}
+
+/// Answers `C`, the reply has the type the client decodes it as
+fn reply<C: Command>(
+    encoding: Encoding,
+    id: Option<u32>,
+    result: Result<C::Response, protocol::Error>,
+) {
+    respond(encoding, id, result)
+}
+
+/// Connection to one client
+struct Link {
+    /// Serial links are, network ones need a token for anything but the handshake and pairing.
+    /// The network server is not built yet, its links will be the untrusted ones. See
+    /// [`Request::needs_token`]
+    trusted: bool,
+    subscription: Option<Subscription>,
+}
+
+/// Client listening for events, a link has at most one
+struct Subscription {
+    id: u32,
+    encoding: Encoding,
+    events: Vec<EventKind>,
+}
+
+impl Subscription {
+    fn send(&self, event: Event) {
+        if event.kind().is_none_or(|kind| self.events.contains(&kind)) {
+            reply::<command::Subscribe>(self.encoding, Some(self.id), Ok(event));
+        }
+    }
+}
+
+/// Checks the token of requests on untrusted links, then handles them. See
+/// [`protocol::pairing::authorize`]
+fn handle_request(
+    controller: &mut RgbControl,
+    pairing: &mut Pairing,
+    link: &mut Link,
+    encoding: Encoding,
+    frame: RequestFrame,
+) {
+    match protocol::pairing::authorize(frame.request, link.trusted, |token| {
+        pairing.is_authorized(token)
+    }) {
+        Ok(request) => {
+            let frame = RequestFrame {
+                id: frame.id,
+                request,
+            };
+            handle_authorized(controller, pairing, link, encoding, frame);
+        }
+        Err(error) => respond::<()>(encoding, Some(frame.id), Err(error)),
+    }
+}
+
+fn handle_authorized(
+    controller: &mut RgbControl,
+    pairing: &mut Pairing,
+    link: &mut Link,
+    encoding: Encoding,
+    frame: RequestFrame,
+) {
+    let id = Some(frame.id);
+    match frame.request {
+        Request::Hello => reply::<command::Hello>(
+            encoding,
+            id,
+            Ok(VersionInfo {
+                protocol_version: PROTOCOL_VERSION,
+                firmware_version: FIRMWARE_VERSION.to_string(),
+                capabilities: CAPABILITIES.to_vec(),
+            }),
+        ),
+        Request::GetEffects => {
+            reply::<command::GetEffects>(encoding, id, Ok(controller.get_effects_name()))
+        }
+        Request::GetEffect => {
+            let name = controller.get_effect_name().to_string();
+            reply::<command::GetEffect>(encoding, id, Ok(name))
+        }
+        Request::GetParameters => {
+            reply::<command::GetParameters>(encoding, id, Ok(controller.get_effect_options()))
+        }
+        Request::GetName => reply::<command::GetName>(encoding, id, Ok(NAME.to_string())),
+        Request::SetEffect(index) => {
+            reply::<command::SetEffect>(encoding, id, controller.set_effect(index))
+        }
+        Request::SetOption(name, parameter_type) => {
+            let result = controller.set_effect_parameter(&name, parameter_type);
+            reply::<command::SetOption>(encoding, id, result)
+        }
+        Request::SetOptions(values) => {
+            reply::<command::SetOptions>(encoding, id, controller.set_effect_parameters(values))
+        }
+        // not answered, the client streams at frame rate
+        Request::StreamColor { color, timeout_ms } => {
+            let timeout = Duration::from_millis(timeout_ms as u64);
+            if let Err(err) = controller.stream_color(color, timeout) {
+                let error = protocol::Error::new(ErrorCode::OutputFailure, err.to_string());
+                controller.raise_error(error);
+            }
+        }
+        Request::Subscribe(events) => {
+            link.subscription = (!events.is_empty()).then(|| Subscription {
+                id: frame.id,
+                encoding,
+                events: events.clone(),
+            });
+            reply::<command::Subscribe>(encoding, id, Ok(Event::Subscribed(events)));
+        }
+        Request::GetDeviceInfo => {
+            reply::<command::GetDeviceInfo>(encoding, id, Ok(device_info::device_info()))
+        }
+        Request::SetPower(on) => {
+            reply::<command::SetPower>(encoding, id, controller.set_power(on))
+        }
+        Request::SetBrightness(brightness) => {
+            reply::<command::SetBrightness>(encoding, id, controller.set_brightness(brightness))
+        }
+        Request::GetPowerState => {
+            reply::<command::GetPowerState>(encoding, id, Ok(controller.power_state()))
+        }
+        Request::ListEffects => {
+            reply::<command::ListEffects>(encoding, id, Ok(controller.list_effects()))
+        }
+        Request::SetEffectById(effect_id) => {
+            let result = controller.set_effect_by_id(&effect_id);
+            reply::<command::SetEffectById>(encoding, id, result)
+        }
+        Request::GetEffectId => {
+            let effect_id = controller.get_effect_id().to_string();
+            reply::<command::GetEffectId>(encoding, id, Ok(effect_id))
+        }
+        // one output for now, zones only tell clients that there are no others
+        Request::GetZones => {
+            let zone = ZoneInfo {
+                id: DEFAULT_ZONE,
+                name: ZONE_NAME.to_string(),
+                channels: controller.channel_layout(),
+            };
+            reply::<command::GetZones>(encoding, id, Ok(vec![zone]))
+        }
+        Request::InZone(zone, request) => {
+            if !request.accepts_zone() {
+                let error = protocol::Error::new(
+                    ErrorCode::MalformedRequest,
+                    "the request doesn't accept a zone",
+                );
+                respond::<()>(encoding, id, Err(error));
+            } else if zone != DEFAULT_ZONE {
+                let error = protocol::Error::new(ErrorCode::UnknownZone, format!("no zone {zone}"));
+                respond::<()>(encoding, id, Err(error));
+            } else {
+                let frame = RequestFrame {
+                    id: frame.id,
+                    request: *request,
+                };
+                handle_authorized(controller, pairing, link, encoding, frame);
+            }
+        }
+        // blinked to whoever is next to the controller, the client only learns it from them.
+        // Never written to a link, the client asking for it may be on one
+        Request::StartPairing => {
+            let result = pairing.start().map(|code| {
+                controller.blink_code(code, Duration::from_millis(CODE_TIMEOUT_MS as u64))
+            });
+            reply::<command::StartPairing>(encoding, id, result)
+        }
+        Request::Pair(code) => {
+            let result = pairing.pair(code);
+            if !pairing.is_pending() {
+                controller.stop_blinking();
+            }
+            reply::<command::Pair>(encoding, id, result)
+        }
+        // only reached from inside another one
+        Request::Authorized(..) => {
+            let error = protocol::Error::new(
+                ErrorCode::MalformedRequest,
+                "authorizations don't nest",
+            );
+            respond::<()>(encoding, id, Err(error));
+        }
+    }
+}
+
 fn main() -> anyhow::Result<()> {
     esp_idf_hal::sys::link_patches();
     //let sys_loop = EspSystemEventLoop::take()?;
@@ -59,10 +293,44 @@ fn main() -> anyhow::Result<()> {
         peripherals.pins.gpio12,
     )?;
 
+    // white dies share one timer, the RGB ones already use three of the four
+    #[cfg(any(feature = "rgbw", feature = "rgbww"))]
+    let ledc_timer_driver_w = LedcTimerDriver::new(peripherals.ledc.timer3, &timer_config)?;
+
+    #[cfg(feature = "rgbww")]
+    let white = WhiteOutput::Dual {
+        pwm_warm: LedcDriver::new(
+            peripherals.ledc.channel3,
+            &ledc_timer_driver_w,
+            peripherals.pins.gpio3,
+        )?,
+        pwm_cold: LedcDriver::new(
+            peripherals.ledc.channel4,
+            &ledc_timer_driver_w,
+            peripherals.pins.gpio4,
+        )?,
+        warm_point: protocol::RGBLedColor::from_kelvin(WARM_WHITE_KELVIN),
+        cold_point: protocol::RGBLedColor::from_kelvin(COLD_WHITE_KELVIN),
+    };
+
+    #[cfg(all(feature = "rgbw", not(feature = "rgbww")))]
+    let white = WhiteOutput::Single {
+        pwm: LedcDriver::new(
+            peripherals.ledc.channel3,
+            &ledc_timer_driver_w,
+            peripherals.pins.gpio3,
+        )?,
+        white_point: protocol::RGBLedColor::from_kelvin(WHITE_KELVIN),
+    };
+
+    #[cfg(not(any(feature = "rgbw", feature = "rgbww")))]
+    let white = WhiteOutput::None;
+
     let controller = Arc::new(Mutex::new(RgbControl::new(
         channel_r,
         channel_g,
         channel_b,
+        white,
         nvs.clone(),
     )));
 
@@ -70,6 +338,13 @@ fn main() -> anyhow::Result<()> {
     controller_lock.init()?;
     drop(controller_lock);
 
+    // without the stored tokens every network client has to pair again, serial still works
+    // Wi-Fi is not brought up, see the server below
+    let mut pairing = Pairing::new(nvs.clone(), false);
+    if let Err(err) = pairing.init() {
+        log::warn!("cannot read paired clients: {err}");
+    }
+
   //  let mut server = Server::new(sys_loop.clone(), peripherals.modem)?;
   //  server
   //      .connect(
@@ -93,67 +368,55 @@ fn main() -> anyhow::Result<()> {
   //  let mut nvs_handle = EspNvs::new(nvs.clone(), "wifi", true)?;
     let stdin = std::io::stdin();
     let mut handle = stdin.lock();
+    let mut decoder = FrameDecoder::new();
+    let mut serial = Link {
+        trusted: true,
+        subscription: None,
+    };
 
     loop {
-        if !handle.has_data_left().unwrap_or_default() {
+        let bytes = match handle.fill_buf() {
+            Ok(bytes) => bytes.to_vec(),
+            Err(_) => Vec::new(),
+        };
+        handle.consume(bytes.len());
+        if bytes.is_empty() {
             FreeRtos::delay_ms(5);
-            let controller = controller.clone();
-            if let Ok(mut lock) = controller.try_lock() {
-                lock.update()?;
-            };
         }
-        let mut buffer = String::new();
-        match handle.read_line(&mut buffer) {
-            Ok(_) => {
-                match serde_json::from_str::<Request>(&buffer) {
-                    Ok(data) => {
-                        let controller = controller.clone();
-                        let mut controller_lock = controller.lock().unwrap();
-                        match data {
-                            Request::GetEffects => {
-                                let json =
-                                    serde_json::to_string(&controller_lock.get_effects_name())
-                                        .unwrap();
-                                println!("{json}");
-                            }
-                            Request::GetEffect => {
-                                let json =
-                                   serde_json::to_string(&controller_lock.get_effect_name())
-                                        .unwrap();
-                                println!("{json}");
-                            }
-                            Request::GetParameters => {
-                                let json =
-                                    serde_json::to_string(&controller_lock.get_effect_options())
-                                        .unwrap();
-                                println!("{json}");
-                            }
-                            Request::GetName => {
-                                let json = serde_json::to_string(&NAME).unwrap();
-                                println!("{json}");
-                            }
-                            Request::SetEffect(index) => {
-                                let json =
-                                    serde_json::to_string(&controller_lock.set_effect(index).is_ok()).unwrap();
-                                println!("{json}");
-                            }
-                            Request::SetOption(name, parameter_type) => {
-                                // TODO: error handling for this request
-                                let json = serde_json::to_string(&controller_lock.set_effect_parameter(&name, parameter_type)).unwrap();
-                                println!("{json}");
-                            }
-                        }
-                    }
-                    Err(e) => println!("Failed to parse JSON: {}", e),
+
+        for byte in bytes {
+            let Some((encoding, frame)) = decoder.push(byte) else {
+                continue;
+            };
+            match framing::decode::<RequestFrame>(encoding, &frame) {
+                Ok(frame) => {
+                    let controller = controller.clone();
+                    let mut controller_lock = controller.lock().unwrap();
+                    let controller_lock = &mut *controller_lock;
+                    handle_request(controller_lock, &mut pairing, &mut serial, encoding, frame);
+                }
+                Err(err) => {
+                    // try to recover at least the ID, so the client can match the error
+                    let id = framing::decode::<RequestHeader>(encoding, &frame)
+                        .ok()
+                        .map(|header| header.id);
+                    respond::<()>(encoding, id, Err(err));
                 }
             }
-            Err(_) => {
-                FreeRtos::delay_ms(5);
-                let controller = controller.clone();
-                if let Ok(mut lock) = controller.try_lock() {
-                    lock.update()?;
-                };
-            },
         }
+
+        // render between chunks too, so a busy link doesn't freeze the effect
+        let controller = controller.clone();
+        if let Ok(mut lock) = controller.try_lock() {
+            // a dropped frame is better than a reboot, subscribers learn about it
+            if let Err(err) = lock.update() {
+                lock.raise_error(protocol::Error::new(ErrorCode::OutputFailure, err.to_string()));
+            }
+            for event in lock.take_events() {
+                if let Some(subscription) = &serial.subscription {
+                    subscription.send(event);
+                }
+            }
+        };
     }
 }
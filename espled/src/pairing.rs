@@ -0,0 +1,167 @@
+use std::time::{Duration, Instant};
+
+use esp_idf_svc::nvs::{EspNvs, EspNvsPartition, NvsDefault};
+use esp_idf_svc::sys;
+use protocol::pairing::{CODE_ATTEMPTS, CODE_LIMIT, CODE_TIMEOUT_MS, LOCKOUT_MS, MAX_LOCKOUT_MS};
+use protocol::{ErrorCode, Token};
+
+/// Paired clients remembered, pairing one more replaces the oldest
+const MAX_TOKENS: usize = 8;
+const NAMESPACE: &str = "pairing";
+
+/// Code shown after [`Pairing::start`], waiting to be traded for a token
+struct PendingCode {
+    code: u32,
+    deadline: Instant,
+}
+
+/// Tokens of the paired network clients, see [`protocol::pairing`]. Stored in NVS, one slot each
+pub struct Pairing {
+    nvs: EspNvsPartition<NvsDefault>,
+    tokens: [Option<Token>; MAX_TOKENS],
+    /// Slot the next token goes to, the oldest one once all are taken
+    next_slot: usize,
+    pending: Option<PendingCode>,
+    /// Wrong codes in a row, whatever code they were meant for. Only pairing resets it, so
+    /// restarting doesn't buy more guesses
+    failures: u32,
+    locked_until: Option<Instant>,
+    /// Whether Wi-Fi or Bluetooth runs, their RF noise is what seeds the RNG
+    radio_on: bool,
+}
+
+impl Pairing {
+    pub fn new(nvs: EspNvsPartition<NvsDefault>, radio_on: bool) -> Self {
+        Self {
+            nvs,
+            tokens: [None; MAX_TOKENS],
+            next_slot: 0,
+            pending: None,
+            failures: 0,
+            locked_until: None,
+            radio_on,
+        }
+    }
+
+    /// Reads the stored tokens
+    pub fn init(&mut self) -> anyhow::Result<()> {
+        let nvs_handle_pairing = EspNvs::new(self.nvs.clone(), NAMESPACE, true)?;
+        let mut buffer = [0u8; 16];
+        for (slot, token) in self.tokens.iter_mut().enumerate() {
+            *token = nvs_handle_pairing
+                .get_blob(&slot_key(slot), &mut buffer)?
+                .and_then(|bytes| bytes.try_into().ok())
+                .map(Token);
+        }
+        self.next_slot = nvs_handle_pairing
+            .get_u8("next_slot")?
+            .map_or(0, |slot| slot as usize % MAX_TOKENS);
+        Ok(())
+    }
+
+    /// New random code. Refused while one is pending or pairing is locked
+    pub fn start(&mut self) -> Result<u32, protocol::Error> {
+        self.check_lockout()?;
+        if self.is_pending() {
+            return Err(unauthorized("another pairing is in progress"));
+        }
+        let mut bytes = [0; 4];
+        fill_random(&mut bytes, self.radio_on);
+        // the modulo bias is far below what three guesses per lockout could exploit
+        let code = u32::from_ne_bytes(bytes) % CODE_LIMIT;
+        self.pending = Some(PendingCode {
+            code,
+            deadline: Instant::now() + Duration::from_millis(CODE_TIMEOUT_MS as u64),
+        });
+        Ok(code)
+    }
+
+    /// Whether a code is waiting, the LEDs keep blinking it until then
+    pub fn is_pending(&self) -> bool {
+        self.pending
+            .as_ref()
+            .is_some_and(|pending| Instant::now() < pending.deadline)
+    }
+
+    /// Trades the pending code for a new token and stores it. Codes are single use, a wrong one
+    /// counts against [`CODE_ATTEMPTS`]
+    pub fn pair(&mut self, code: u32) -> Result<Token, protocol::Error> {
+        self.check_lockout()?;
+        if !self.is_pending() {
+            self.pending = None;
+            return Err(unauthorized("no pairing code pending"));
+        }
+        if self.pending.as_ref().is_some_and(|pending| pending.code != code) {
+            self.failures += 1;
+            if self.failures % CODE_ATTEMPTS as u32 == 0 {
+                self.pending = None;
+                self.locked_until = Some(Instant::now() + self.lockout());
+            }
+            return Err(unauthorized("wrong pairing code"));
+        }
+        self.pending = None;
+        self.failures = 0;
+
+        let mut token = Token::default();
+        fill_random(&mut token.0, self.radio_on);
+
+        let slot = self.next_slot;
+        let next_slot = (slot + 1) % MAX_TOKENS;
+        let result = EspNvs::new(self.nvs.clone(), NAMESPACE, true).and_then(|nvs_handle_pairing| {
+            nvs_handle_pairing.set_blob(&slot_key(slot), &token.0)?;
+            nvs_handle_pairing.set_u8("next_slot", next_slot as u8)
+        });
+        result.map_err(|err| protocol::Error::new(ErrorCode::StorageFailure, err.to_string()))?;
+        self.tokens[slot] = Some(token);
+        self.next_slot = next_slot;
+        Ok(token)
+    }
+
+    /// Doubles with every lockout in a row, see [`LOCKOUT_MS`]
+    fn lockout(&self) -> Duration {
+        let lockouts = self.failures / CODE_ATTEMPTS as u32;
+        let lockout = (LOCKOUT_MS as u64) << lockouts.saturating_sub(1).min(16);
+        Duration::from_millis(lockout.min(MAX_LOCKOUT_MS as u64))
+    }
+
+    fn check_lockout(&self) -> Result<(), protocol::Error> {
+        let now = Instant::now();
+        match self.locked_until {
+            Some(until) if now < until => Err(unauthorized(format!(
+                "too many wrong codes, pairing is locked for {} s",
+                (until - now).as_secs() + 1
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn is_authorized(&self, token: &Token) -> bool {
+        // every slot is compared, so the time doesn't tell which one matched
+        self.tokens
+            .iter()
+            .flatten()
+            .fold(false, |authorized, stored| stored.matches(token) | authorized)
+    }
+}
+
+/// True random numbers. With the radio off the RNG falls back to the bootloader's entropy
+/// source, which must not run alongside Wi-Fi. See "Random Number Generation" in the ESP-IDF docs
+fn fill_random(bytes: &mut [u8], radio_on: bool) {
+    unsafe {
+        if radio_on {
+            sys::esp_fill_random(bytes.as_mut_ptr().cast(), bytes.len());
+        } else {
+            sys::bootloader_random_enable();
+            sys::esp_fill_random(bytes.as_mut_ptr().cast(), bytes.len());
+            sys::bootloader_random_disable();
+        }
+    }
+}
+
+fn unauthorized(message: impl Into<String>) -> protocol::Error {
+    protocol::Error::new(ErrorCode::Unauthorized, message)
+}
+
+fn slot_key(slot: usize) -> String {
+    format!("token{slot}")
+}
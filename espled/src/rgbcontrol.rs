@@ -1,18 +1,75 @@
-use std::{collections::HashMap, time::Instant};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
 
 use crate::effects::{self, Effect};
 use esp_idf_hal::ledc::LedcDriver;
 use esp_idf_svc::nvs::{EspNvs, EspNvsPartition, NvsDefault};
-use protocol::{ParameterTypes, RGBLedColor};
+use protocol::pairing::CODE_DIGITS;
+use protocol::{
+    ChannelLayout, EffectInfo, ErrorCode, Event, Palette, ParameterDescriptor, ParameterTypes,
+    PowerState, RGBLedColor, RGBWLedColor, RGBWWLedColor,
+};
+
+/// How long switching the output on or off takes
+const POWER_FADE: Duration = Duration::from_millis(500);
+/// [`Effect::id`]s in the order of the effect list while the selection was stored as an index.
+/// Only for migrating that index, never change it
+const LEGACY_EFFECT_ORDER: &[&str] = &["direct", "hue_rotate", "decay", "palette", "temperature"];
+/// [`Effect::id`]s with the [`Effect::name`] their parameters were stored under before.
+/// Only for migrating them, never change it. "Color Temperature" was too long for a namespace,
+/// nothing was ever stored under it
+const LEGACY_PARAMETER_NAMESPACES: &[(&str, &str)] = &[
+    ("direct", "Direct"),
+    ("hue_rotate", "Hue Rotate"),
+    ("decay", "Decay"),
+    ("palette", "Palette"),
+];
+/// One flash of a pairing code digit, lit for the first half
+const CODE_FLASH: Duration = Duration::from_millis(400);
+const CODE_DIGIT_PAUSE: Duration = Duration::from_millis(1200);
+/// Before the code starts over
+const CODE_PAUSE: Duration = Duration::from_millis(3000);
+
+/// White dies of the strip, next to the RGB ones. Effects render RGB and the white part is
+/// extracted right before output, see [`protocol::white`]
+pub enum WhiteOutput {
+    None,
+    /// One white die, `white_point` is the colour it shows at full duty
+    Single {
+        pwm: LedcDriver<'static>,
+        white_point: RGBLedColor,
+    },
+    /// Warm and cold white dies
+    Dual {
+        pwm_warm: LedcDriver<'static>,
+        pwm_cold: LedcDriver<'static>,
+        warm_point: RGBLedColor,
+        cold_point: RGBLedColor,
+    },
+}
 
 pub struct RgbControl {
     pwm_r: LedcDriver<'static>,
     pwm_g: LedcDriver<'static>,
     pwm_b: LedcDriver<'static>,
+    white: WhiteOutput,
     nvs: EspNvsPartition<NvsDefault>,
     effects: Vec<Box<dyn Effect>>,
     selected_effect_index: usize,
     dt: Instant,
+    /// Queued for subscribers, see [`RgbControl::take_events`]
+    events: Vec<Event>,
+    /// Last error queued as an event, so a failure repeating every frame is reported once
+    last_error: Option<protocol::Error>,
+    /// Streamed colour shown instead of the effect until the deadline passes
+    stream: Option<(RGBLedColor, Instant)>,
+    power: bool,
+    /// Master brightness, applied on top of whatever is shown
+    brightness: f32,
+    /// Follows `power` from 0.0 (off) to 1.0 (on) over [`POWER_FADE`]
+    power_level: f32,
+    /// Pairing code blinked instead of anything else, with when it started and stops
+    pairing_code: Option<(u32, Instant, Instant)>,
 }
 
 impl RgbControl {
@@ -20,44 +77,140 @@ impl RgbControl {
         pwm_r: LedcDriver<'static>,
         pwm_g: LedcDriver<'static>,
         pwm_b: LedcDriver<'static>,
+        white: WhiteOutput,
         nvs: EspNvsPartition<NvsDefault>,
     ) -> Self {
         Self {
             pwm_r,
             pwm_g,
             pwm_b,
+            white,
             nvs,
             effects: vec![
                 Box::new(effects::direct::Direct::new()),
                 Box::new(effects::huerotate::HueRotate::new()),
-                Box::new(effects::decay::Decay::new())
+                Box::new(effects::decay::Decay::new()),
+                Box::new(effects::palette::PaletteCycle::new()),
+                Box::new(effects::temperature::Temperature::new()),
             ],
             selected_effect_index: 0,
             dt: Instant::now(),
+            events: Vec::new(),
+            last_error: None,
+            stream: None,
+            power: true,
+            brightness: 1.0,
+            // fades in after boot as well
+            power_level: 0.0,
+            pairing_code: None,
         }
     }
 
     pub fn init(&mut self) -> anyhow::Result<()> {
         let nvs_handle_settings = EspNvs::new(self.nvs.clone(), "settings", true)?;
-        self.selected_effect_index =
-            if let Ok(effect_index) = nvs_handle_settings.get_u8("effect_index") {
-                effect_index.unwrap_or_default() as usize
-            } else {
+        let mut buffer = [0; effects::MAX_ID_LENGTH + 1];
+        let stored_id = match nvs_handle_settings.get_str("effect_id", &mut buffer) {
+            Ok(Some(id)) => Some(id.to_string()),
+            _ => migrate_effect_index(&nvs_handle_settings),
+        };
+        self.selected_effect_index = match stored_id {
+            Some(id) => self.effect_position(&id).unwrap_or_else(|| {
+                log::warn!("stored effect {id} doesn't exist, starting the first one");
                 0
-            };
+            }),
+            None => 0,
+        };
+        if let Ok(Some(power)) = nvs_handle_settings.get_u8("power") {
+            self.power = power != 0;
+        }
+        if let Ok(Some(brightness)) = nvs_handle_settings.get_u32("brightness") {
+            let brightness = f32::from_bits(brightness);
+            if !brightness.is_nan() {
+                self.brightness = brightness.clamp(0.0, 1.0);
+            }
+        }
+
+        if !matches!(nvs_handle_settings.get_u8("params_by_id"), Ok(Some(_))) {
+            let result = self
+                .migrate_parameter_namespaces()
+                .and_then(|()| Ok(nvs_handle_settings.set_u8("params_by_id", 1)?));
+            if let Err(err) = result {
+                // what is left in the old namespaces is migrated again on the next boot
+                log::warn!("cannot migrate the stored parameters: {err}");
+            }
+        }
+
+        self.init_effect();
+        Ok(())
+    }
 
-        self.effects[self.selected_effect_index].init(self.nvs.clone());
+    /// Moves parameters stored under the [`Effect::name`] of older firmware to the
+    /// [`Effect::id`], see [`LEGACY_PARAMETER_NAMESPACES`]
+    fn migrate_parameter_namespaces(&mut self) -> anyhow::Result<()> {
+        for effect in self.effects.iter_mut() {
+            let Some((_, name)) = LEGACY_PARAMETER_NAMESPACES
+                .iter()
+                .find(|(id, _)| *id == effect.id())
+            else {
+                continue;
+            };
+            // opening read only fails for a namespace that was never written
+            if EspNvs::new(self.nvs.clone(), name, false).is_err() {
+                continue;
+            }
+            log::info!("migrating the stored parameters of {name} to {}", effect.id());
+            let nvs_handle_legacy = EspNvs::new(self.nvs.clone(), name, true)?;
+            // keeps what an interrupted migration already moved
+            effect.init(self.nvs.clone())?;
+            effect.load(&nvs_handle_legacy);
+            effect.save(self.nvs.clone())?;
+            for ParameterDescriptor { key, .. } in effect.get_parameters() {
+                nvs_handle_legacy.remove(&key)?;
+            }
+        }
         Ok(())
     }
 
-    pub fn set_effect(&mut self, index: usize) -> anyhow::Result<()> {
-        if index > self.effects.len() - 1 {
-           anyhow::bail!("Effect out of range")
+    /// Loads the parameters of the selected effect. Failing to is not fatal, the effect runs with
+    /// its defaults
+    fn init_effect(&mut self) {
+        let effect = &mut self.effects[self.selected_effect_index];
+        if let Err(err) = effect.init(self.nvs.clone()) {
+            log::warn!("cannot read the parameters of {}: {err}", effect.id());
+        }
+    }
+
+    fn effect_position(&self, id: &str) -> Option<usize> {
+        self.effects.iter().position(|effect| effect.id() == id)
+    }
+
+    pub fn set_effect_by_id(&mut self, id: &str) -> Result<(), protocol::Error> {
+        let index = self.effect_position(id).ok_or_else(|| {
+            protocol::Error::new(ErrorCode::UnknownEffect, format!("no effect {id}"))
+        })?;
+        self.set_effect(index)
+    }
+
+    /// The selection is stored by [`Effect::id`], so it survives changes to the effect list
+    pub fn set_effect(&mut self, index: usize) -> Result<(), protocol::Error> {
+        if index >= self.effects.len() {
+            return Err(protocol::Error::new(
+                ErrorCode::UnknownEffect,
+                format!("effect index {index} is out of range (0..{})", self.effects.len()),
+            ));
         }
+        let result = EspNvs::new(self.nvs.clone(), "settings", true).and_then(|nvs_handle_settings| {
+            nvs_handle_settings.set_str("effect_id", self.effects[index].id())
+        });
+        // the previous effect keeps running when the selection can't be stored
+        result.map_err(|err| self.storage_failure(err.to_string()))?;
         self.selected_effect_index = index;
-        let nvs_handle_settings = EspNvs::new(self.nvs.clone(), "settings", true)?;
-        nvs_handle_settings.set_u8("effect_index", self.selected_effect_index as u8)?;
-        self.effects[self.selected_effect_index].init(self.nvs.clone());
+        self.init_effect();
+        self.events.push(Event::EffectChanged {
+            id: self.get_effect_id().to_string(),
+            name: self.get_effect_name().to_string(),
+            parameters: self.get_effect_options(),
+        });
         Ok(())
     }
 
@@ -65,33 +218,359 @@ impl RgbControl {
         self.effects[self.selected_effect_index].name()
     }
 
-    pub fn get_effect_options(&self) -> HashMap<String, ParameterTypes> {
+    pub fn get_effect_id(&self) -> &'static str {
+        self.effects[self.selected_effect_index].id()
+    }
+
+    pub fn list_effects(&self) -> Vec<EffectInfo> {
+        self.effects
+            .iter()
+            .map(|effect| EffectInfo {
+                id: effect.id().to_string(),
+                name: effect.name().to_string(),
+            })
+            .collect()
+    }
+
+    pub fn get_effect_options(&self) -> Vec<ParameterDescriptor> {
         self.effects[self.selected_effect_index].get_parameters()
     }
 
-    pub fn set_effect_parameter(&mut self, name: &str, value: ParameterTypes) -> bool {
-        let res = self.effects[self.selected_effect_index].set_parameter(name, value);
-        self.effects[self.selected_effect_index].save(self.nvs.clone());
-        return res;
+    pub fn set_effect_parameter(
+        &mut self,
+        name: &str,
+        value: ParameterTypes,
+    ) -> Result<ParameterTypes, protocol::Error> {
+        let effect = &mut self.effects[self.selected_effect_index];
+        let value = validate_parameter(effect.as_ref(), &effect.get_parameters(), name, value)?;
+
+        let previous = effects::find_parameter(&effect.get_parameters(), name).cloned();
+        effect.set_parameter(name, value.clone());
+        if let Err(err) = effect.save(self.nvs.clone()) {
+            // like a failed batch, the value doesn't stay live when it can't be stored
+            if let Some(previous) = previous {
+                effect.set_parameter(name, previous);
+            }
+            return Err(self.storage_failure(err.to_string()));
+        }
+        self.events.push(Event::ParameterChanged {
+            key: name.to_string(),
+            value: value.clone(),
+        });
+        Ok(value)
+    }
+
+    /// Validates every value before applying any of them, then saves once. If a value is rejected
+    /// nothing changes and the valid ones are reported as [`ErrorCode::Aborted`], if saving fails
+    /// the previous values are restored
+    pub fn set_effect_parameters(
+        &mut self,
+        values: BTreeMap<String, ParameterTypes>,
+    ) -> Result<BTreeMap<String, Result<ParameterTypes, protocol::Error>>, protocol::Error> {
+        let effect = &mut self.effects[self.selected_effect_index];
+        let parameters = effect.get_parameters();
+        let results: BTreeMap<_, _> = values
+            .into_iter()
+            .map(|(name, value)| {
+                let result = validate_parameter(effect.as_ref(), &parameters, &name, value);
+                (name, result)
+            })
+            .collect();
+
+        if results.values().any(Result::is_err) {
+            return Ok(results
+                .into_iter()
+                .map(|(name, result)| {
+                    let result = result.and_then(|_| {
+                        Err(protocol::Error::new(
+                            ErrorCode::Aborted,
+                            "another parameter of the batch was rejected",
+                        ))
+                    });
+                    (name, result)
+                })
+                .collect());
+        }
+
+        for (name, value) in results.iter() {
+            if let Ok(value) = value {
+                effect.set_parameter(name, value.clone());
+            }
+        }
+        if let Err(err) = effect.save(self.nvs.clone()) {
+            // nothing of the batch stays live when it can't be stored either. Keys written before
+            // the failure are put back too, as far as the flash still takes them
+            for ParameterDescriptor { key, value, .. } in parameters {
+                effect.set_parameter(&key, value);
+            }
+            if let Err(err) = effect.save(self.nvs.clone()) {
+                log::warn!("cannot restore the stored parameters of {}: {err}", effect.id());
+            }
+            return Err(self.storage_failure(err.to_string()));
+        }
+        // only announced once stored, subscribers would otherwise show a value lost on reboot
+        for (name, value) in results.iter() {
+            if let Ok(value) = value {
+                self.events.push(Event::ParameterChanged {
+                    key: name.clone(),
+                    value: value.clone(),
+                });
+            }
+        }
+        Ok(results)
+    }
+
+    /// Events since the last call, in the order they happened
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Queues `error` as an event, unless it repeats the previous one
+    pub fn raise_error(&mut self, error: protocol::Error) {
+        if self.last_error.as_ref() != Some(&error) {
+            self.events.push(Event::Error(error.clone()));
+            self.last_error = Some(error);
+        }
+    }
+
+    fn storage_failure(&mut self, message: String) -> protocol::Error {
+        let error = protocol::Error::new(ErrorCode::StorageFailure, message);
+        self.raise_error(error.clone());
+        error
+    }
+
+    pub fn get_effects_name(&self) -> Vec<String> {
+        self.effects.iter().map(|x| x.name().to_string()).collect()
+    }
+
+    pub fn channel_layout(&self) -> ChannelLayout {
+        match self.white {
+            WhiteOutput::None => ChannelLayout::Rgb,
+            WhiteOutput::Single { .. } => ChannelLayout::Rgbw,
+            WhiteOutput::Dual { .. } => ChannelLayout::Rgbww,
+        }
+    }
+
+    pub fn power_state(&self) -> PowerState {
+        PowerState {
+            on: self.power,
+            brightness: self.brightness,
+        }
+    }
+
+    /// Switches the output, fading over [`POWER_FADE`]. The effect keeps running underneath
+    pub fn set_power(&mut self, on: bool) -> Result<bool, protocol::Error> {
+        self.power = on;
+        let result = EspNvs::new(self.nvs.clone(), "settings", true)
+            .and_then(|nvs_handle_settings| nvs_handle_settings.set_u8("power", on as u8));
+        result.map_err(|err| self.storage_failure(err.to_string()))?;
+        self.events.push(Event::PowerChanged(on));
+        Ok(on)
+    }
+
+    /// Returns the brightness actually applied, clamped to [0.0, 1.0]
+    pub fn set_brightness(&mut self, brightness: f32) -> Result<f32, protocol::Error> {
+        if brightness.is_nan() {
+            return Err(protocol::Error::new(
+                ErrorCode::OutOfRange,
+                "brightness is not a number",
+            ));
+        }
+        self.brightness = brightness.clamp(0.0, 1.0);
+        let result = EspNvs::new(self.nvs.clone(), "settings", true).and_then(|nvs_handle_settings| {
+            nvs_handle_settings.set_u32("brightness", self.brightness.to_bits())
+        });
+        result.map_err(|err| self.storage_failure(err.to_string()))?;
+        self.events.push(Event::BrightnessChanged(self.brightness));
+        Ok(self.brightness)
+    }
+
+    /// Shows `color` instead of the effect for `timeout`, nothing is persisted
+    pub fn stream_color(&mut self, color: RGBLedColor, timeout: Duration) -> anyhow::Result<()> {
+        self.stream = Some((color, Instant::now() + timeout));
+        self.update()
+    }
+
+    /// Blinks `code` for `timeout`, so it can be read off the strip. See [`code_blink_lit`]
+    pub fn blink_code(&mut self, code: u32, timeout: Duration) {
+        let now = Instant::now();
+        self.pairing_code = Some((code, now, now + timeout));
     }
 
-    pub fn get_effects_name(&self) -> Vec<&str> {
-        self.effects.iter().map(|x| x.name()).collect()
+    pub fn stop_blinking(&mut self) {
+        self.pairing_code = None;
     }
 
     pub fn update(&mut self) -> anyhow::Result<()> {
-        self.effects[self.selected_effect_index].update(self.dt.elapsed().as_secs_f32());
+        let delta = self.dt.elapsed().as_secs_f32();
+        self.effects[self.selected_effect_index].update(delta);
         self.dt = Instant::now();
-        let mut color = self.effects[self.selected_effect_index].render();
+
+        let step = delta / POWER_FADE.as_secs_f32();
+        self.power_level = if self.power {
+            (self.power_level + step).min(1.0)
+        } else {
+            (self.power_level - step).max(0.0)
+        };
+
+        // the effect keeps running underneath, so it continues smoothly once the stream stops
+        let mut color = match self.stream {
+            Some((color, deadline)) if self.dt < deadline => color,
+            _ => {
+                self.stream = None;
+                self.effects[self.selected_effect_index].render()
+            }
+        };
+        // power and brightness go on top of the effect and the stream alike
+        color = color.scale(self.brightness * self.power_level);
+        // the pairing code is shown at full brightness, even with the output off
+        match self.pairing_code {
+            Some((code, started, deadline)) if self.dt < deadline => {
+                let lit = code_blink_lit(code, self.dt - started);
+                let level = if lit { 255 } else { 0 };
+                color = RGBLedColor::new(level, level, level);
+            }
+            _ => self.pairing_code = None,
+        }
         color.gamma_correct(1.3);
-        self.set_color_pwm(color)
+        self.set_color_pwm(color)?;
+        self.last_error = None;
+        Ok(())
     }
 
     fn set_color_pwm(&mut self, color: RGBLedColor) -> anyhow::Result<()> {
         let max_duty = self.pwm_r.get_max_duty();
+        let color = match &mut self.white {
+            WhiteOutput::None => color,
+            WhiteOutput::Single { pwm, white_point } => {
+                let color = RGBWLedColor::from_rgb(color, *white_point);
+                pwm.set_duty(color.white as u32 * max_duty / 255)?;
+                RGBLedColor::new(color.red, color.green, color.blue)
+            }
+            WhiteOutput::Dual {
+                pwm_warm,
+                pwm_cold,
+                warm_point,
+                cold_point,
+            } => {
+                let color = RGBWWLedColor::from_rgb(color, *warm_point, *cold_point);
+                pwm_warm.set_duty(color.warm_white as u32 * max_duty / 255)?;
+                pwm_cold.set_duty(color.cold_white as u32 * max_duty / 255)?;
+                RGBLedColor::new(color.red, color.green, color.blue)
+            }
+        };
         self.pwm_r.set_duty(color.red as u32 * max_duty / 255)?;
         self.pwm_g.set_duty(color.green as u32 * max_duty / 255)?;
         self.pwm_b.set_duty(color.blue as u32 * max_duty / 255)?;
         Ok(())
     }
 }
+
+/// Whether the LEDs are lit `elapsed` into blinking `code`: each digit, most significant first,
+/// as that many flashes, ten for a zero. Then a pause and the code starts over
+fn code_blink_lit(code: u32, elapsed: Duration) -> bool {
+    let flashes = (0..CODE_DIGITS).rev().map(|position| match code / 10u32.pow(position) % 10 {
+        0 => 10,
+        digit => digit,
+    });
+    let period = flashes
+        .clone()
+        .map(|count| CODE_FLASH * count + CODE_DIGIT_PAUSE)
+        .sum::<Duration>()
+        + CODE_PAUSE;
+
+    let mut time = Duration::from_nanos((elapsed.as_nanos() % period.as_nanos()) as u64);
+    for count in flashes {
+        let digit = CODE_FLASH * count;
+        if time < digit {
+            return time.as_nanos() % CODE_FLASH.as_nanos() < CODE_FLASH.as_nanos() / 2;
+        }
+        if time < digit + CODE_DIGIT_PAUSE {
+            return false;
+        }
+        time -= digit + CODE_DIGIT_PAUSE;
+    }
+    false
+}
+
+/// Replaces the `effect_index` of older firmware with the ID of the effect it meant,
+/// returns that ID
+fn migrate_effect_index(nvs_handle_settings: &EspNvs<NvsDefault>) -> Option<String> {
+    let index = nvs_handle_settings.get_u8("effect_index").ok()??;
+    let id = LEGACY_EFFECT_ORDER.get(index as usize)?;
+    log::info!("migrating stored effect index {index} to {id}");
+    if let Err(err) = nvs_handle_settings
+        .set_str("effect_id", id)
+        .and_then(|_| nvs_handle_settings.remove("effect_index"))
+    {
+        // the index is still there, migration is tried again on the next boot
+        log::warn!("cannot migrate the stored effect: {err}");
+    }
+    Some(id.to_string())
+}
+
+/// Checks `value` against the descriptor of `name`, returns the value to apply
+fn validate_parameter(
+    effect: &dyn Effect,
+    parameters: &[ParameterDescriptor],
+    name: &str,
+    value: ParameterTypes,
+) -> Result<ParameterTypes, protocol::Error> {
+    let Some(descriptor) = parameters.iter().find(|descriptor| descriptor.key == name) else {
+        return Err(protocol::Error::new(
+            ErrorCode::UnknownParameter,
+            format!("effect {} has no parameter {name}", effect.name()),
+        ));
+    };
+    let value = match (&descriptor.value, value) {
+        (current, value) if !current.same_type(&value) => {
+            return Err(protocol::Error::new(
+                ErrorCode::TypeMismatch,
+                format!("parameter {name} expects {current:?}, got {value:?}"),
+            ))
+        }
+        // clients only pick an option, the list itself belongs to the effect
+        (ParameterTypes::Choice { options, .. }, ParameterTypes::Choice { selected, .. }) => {
+            if selected >= options.len() {
+                return Err(protocol::Error::new(
+                    ErrorCode::OutOfRange,
+                    format!("parameter {name} has no option {selected}"),
+                ));
+            }
+            ParameterTypes::Choice {
+                selected,
+                options: options.clone(),
+            }
+        }
+        (_, ParameterTypes::Text(text)) if text.len() > effects::MAX_TEXT_LENGTH => {
+            return Err(protocol::Error::new(
+                ErrorCode::OutOfRange,
+                format!(
+                    "parameter {name} is limited to {} bytes",
+                    effects::MAX_TEXT_LENGTH
+                ),
+            ))
+        }
+        (_, ParameterTypes::Palette(palette)) => {
+            if palette.stops.len() > Palette::MAX_STOPS {
+                return Err(protocol::Error::new(
+                    ErrorCode::OutOfRange,
+                    format!("parameter {name} is limited to {} stops", Palette::MAX_STOPS),
+                ));
+            }
+            if let Some(stop) = palette
+                .stops
+                .iter()
+                .find(|stop| !(0.0..=1.0).contains(&stop.position))
+            {
+                return Err(protocol::Error::new(
+                    ErrorCode::OutOfRange,
+                    format!("stop position {} is outside of 0..1", stop.position),
+                ));
+            }
+            ParameterTypes::Palette(Palette::new(palette.stops, palette.interpolation))
+        }
+        (_, value) => descriptor.clamp(value),
+    };
+    Ok(value)
+}
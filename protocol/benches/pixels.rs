@@ -0,0 +1,131 @@
+//! Bytes per frame of the pixel encodings against a list of colours, and how long encoding and
+//! decoding take. The sizes are printed before the timings.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use protocol::framing::{self, Encoding};
+use protocol::{PixelFrame, RGBLedColor};
+use serde::Serialize;
+
+const PIXELS: usize = 300;
+
+/// Previous and current frame of a typical animation
+struct Scene {
+    name: &'static str,
+    previous: Vec<RGBLedColor>,
+    pixels: Vec<RGBLedColor>,
+}
+
+fn rainbow(offset: usize) -> Vec<RGBLedColor> {
+    (0..PIXELS)
+        .map(|index| {
+            let hue = (index + offset) as f32 * 360.0 / PIXELS as f32;
+            RGBLedColor::from_hsv(hue, 1.0, 1.0)
+        })
+        .collect()
+}
+
+/// A few solid segments, a progress bar or a zoned scene
+fn segments(filled: usize) -> Vec<RGBLedColor> {
+    (0..PIXELS)
+        .map(|index| match index {
+            _ if index < filled => RGBLedColor::new(0, 200, 80),
+            _ if index < PIXELS / 2 => RGBLedColor::new(10, 10, 10),
+            _ => RGBLedColor::new(255, 120, 0),
+        })
+        .collect()
+}
+
+/// Dark strip with a few pixels lit, some of them move each frame
+fn sparkle(seed: u32) -> Vec<RGBLedColor> {
+    let mut state = seed;
+    (0..PIXELS)
+        .map(|_| {
+            // xorshift, good enough to scatter the pixels
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            if state.is_multiple_of(20) {
+                RGBLedColor::new(255, 255, 255)
+            } else {
+                RGBLedColor::new(0, 0, 0)
+            }
+        })
+        .collect()
+}
+
+fn scenes() -> Vec<Scene> {
+    let mut sparkle_next = sparkle(7);
+    for pixel in sparkle_next.iter_mut().step_by(29) {
+        *pixel = RGBLedColor::new(80, 80, 255);
+    }
+    vec![
+        Scene {
+            name: "rainbow",
+            previous: rainbow(0),
+            pixels: rainbow(1),
+        },
+        Scene {
+            name: "segments",
+            previous: segments(40),
+            pixels: segments(41),
+        },
+        Scene {
+            name: "sparkle",
+            previous: sparkle(7),
+            pixels: sparkle_next,
+        },
+    ]
+}
+
+fn frame_sizes<T: Serialize>(message: &T) -> (usize, usize) {
+    (
+        framing::encode(Encoding::Json, message).len(),
+        framing::encode(Encoding::Binary, message).len(),
+    )
+}
+
+fn print_sizes(scenes: &[Scene]) {
+    println!("bytes per frame of {PIXELS} pixels, JSON / binary");
+    for scene in scenes {
+        let candidates = [
+            ("colour list", frame_sizes(&scene.pixels)),
+            ("packed", frame_sizes(&PixelFrame::packed(&scene.pixels))),
+            (
+                "run length",
+                frame_sizes(&PixelFrame::run_length(&scene.pixels)),
+            ),
+            (
+                "delta",
+                frame_sizes(&PixelFrame::delta(&scene.previous, &scene.pixels)),
+            ),
+        ];
+        println!("{}:", scene.name);
+        for (name, (json, binary)) in candidates {
+            println!("  {name:<12} {json:>6} / {binary:>5}");
+        }
+    }
+    println!();
+}
+
+fn bench(c: &mut Criterion) {
+    let scenes = scenes();
+    print_sizes(&scenes);
+
+    for scene in &scenes {
+        let mut group = c.benchmark_group(scene.name);
+        group.bench_function("encode", |b| {
+            b.iter(|| PixelFrame::encode(Some(black_box(&scene.previous)), &scene.pixels))
+        });
+        let frame = PixelFrame::encode(Some(&scene.previous), &scene.pixels);
+        group.bench_function("decode", |b| {
+            b.iter(|| black_box(&frame).decode(&scene.previous).unwrap())
+        });
+        group.bench_function("colour list to JSON", |b| {
+            b.iter(|| framing::encode(Encoding::Json, black_box(&scene.pixels)))
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
@@ -0,0 +1,215 @@
+//! Colour mixing in linear RGB and Oklab.
+//!
+//! Mixing sRGB bytes directly darkens and muddies the midpoints, so every operation here converts
+//! to linear light first. Only `core` and `libm` are used, so the same code runs in firmware
+//! effects and in the GUI preview.
+
+use crate::RGBLedColor;
+
+/// Colour in the Oklab perceptual space, `l` is lightness in [0.0, 1.0]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Oklab {
+    pub l: f32,
+    pub a: f32,
+    pub b: f32,
+}
+
+/// sRGB transfer function, byte to linear light in [0.0, 1.0]
+pub fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        libm::powf((value + 0.055) / 1.055, 2.4)
+    }
+}
+
+/// Inverse of [`srgb_to_linear`], `value` is clamped to [0.0, 1.0]
+pub fn linear_to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let encoded = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * libm::powf(value, 1.0 / 2.4) - 0.055
+    };
+    libm::roundf(encoded * 255.0).clamp(0.0, 255.0) as u8
+}
+
+fn lerp_f32(from: f32, to: f32, t: f32) -> f32 {
+    from + (to - from) * t
+}
+
+impl RGBLedColor {
+    /// Channels in linear light, [0.0, 1.0]
+    pub fn to_linear(&self) -> [f32; 3] {
+        [
+            srgb_to_linear(self.red),
+            srgb_to_linear(self.green),
+            srgb_to_linear(self.blue),
+        ]
+    }
+
+    /// Inverse of [`RGBLedColor::to_linear`], channels are clamped to [0.0, 1.0]
+    pub fn from_linear([red, green, blue]: [f32; 3]) -> Self {
+        Self::new(
+            linear_to_srgb(red),
+            linear_to_srgb(green),
+            linear_to_srgb(blue),
+        )
+    }
+
+    #[allow(clippy::excessive_precision)]
+    pub fn to_oklab(&self) -> Oklab {
+        let [red, green, blue] = self.to_linear();
+        let l = 0.4122214708 * red + 0.5363325363 * green + 0.0514459929 * blue;
+        let m = 0.2119034982 * red + 0.6806995451 * green + 0.1073969566 * blue;
+        let s = 0.0883024619 * red + 0.2817188376 * green + 0.6299787005 * blue;
+
+        let (l, m, s) = (libm::cbrtf(l), libm::cbrtf(m), libm::cbrtf(s));
+        Oklab {
+            l: 0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+            a: 1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+            b: 0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+        }
+    }
+
+    /// Inverse of [`RGBLedColor::to_oklab`], colours outside of sRGB are clamped per channel
+    #[allow(clippy::excessive_precision)]
+    pub fn from_oklab(color: Oklab) -> Self {
+        let l = color.l + 0.3963377774 * color.a + 0.2158037573 * color.b;
+        let m = color.l - 0.1055613458 * color.a - 0.0638541728 * color.b;
+        let s = color.l - 0.0894841775 * color.a - 1.2914855480 * color.b;
+
+        let (l, m, s) = (l * l * l, m * m * m, s * s * s);
+        Self::from_linear([
+            4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+            -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+            -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+        ])
+    }
+
+    /// Mixes towards `other` in linear RGB, `t` is clamped to [0.0, 1.0]
+    pub fn lerp(&self, other: RGBLedColor, t: f32) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        let [from, to] = [self.to_linear(), other.to_linear()];
+        Self::from_linear([
+            lerp_f32(from[0], to[0], t),
+            lerp_f32(from[1], to[1], t),
+            lerp_f32(from[2], to[2], t),
+        ])
+    }
+
+    /// Mixes towards `other` in Oklab, keeps the perceived lightness even between the two.
+    /// `t` is clamped to [0.0, 1.0]
+    pub fn mix_oklab(&self, other: RGBLedColor, t: f32) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        let [from, to] = [self.to_oklab(), other.to_oklab()];
+        Self::from_oklab(Oklab {
+            l: lerp_f32(from.l, to.l, t),
+            a: lerp_f32(from.a, to.a, t),
+            b: lerp_f32(from.b, to.b, t),
+        })
+    }
+
+    /// Draws this colour with `alpha` coverage on top of `background`
+    pub fn over(&self, background: RGBLedColor, alpha: f32) -> Self {
+        background.lerp(*self, alpha)
+    }
+
+    /// Sum of the light of both colours, saturating at white
+    pub fn add(&self, other: RGBLedColor) -> Self {
+        self.combine(other, |a, b| a + b)
+    }
+
+    /// Product of both colours, always darker than either
+    pub fn multiply(&self, other: RGBLedColor) -> Self {
+        self.combine(other, |a, b| a * b)
+    }
+
+    /// Inverse of multiplying the inverses, always lighter than either
+    pub fn screen(&self, other: RGBLedColor) -> Self {
+        self.combine(other, |a, b| 1.0 - (1.0 - a) * (1.0 - b))
+    }
+
+    /// Applies `operation` to each pair of linear channels
+    fn combine(&self, other: RGBLedColor, operation: impl Fn(f32, f32) -> f32) -> Self {
+        let [a, b] = [self.to_linear(), other.to_linear()];
+        Self::from_linear([
+            operation(a[0], b[0]),
+            operation(a[1], b[1]),
+            operation(a[2], b[2]),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: RGBLedColor = RGBLedColor::new(0, 0, 0);
+    const WHITE: RGBLedColor = RGBLedColor::new(255, 255, 255);
+    const RED: RGBLedColor = RGBLedColor::new(255, 0, 0);
+    const GREEN: RGBLedColor = RGBLedColor::new(0, 255, 0);
+    const BLUE: RGBLedColor = RGBLedColor::new(0, 0, 255);
+
+    #[test]
+    fn transfer_round_trip() {
+        for value in 0..=255 {
+            assert_eq!(linear_to_srgb(srgb_to_linear(value)), value);
+        }
+    }
+
+    #[test]
+    fn oklab_round_trip() {
+        for color in [
+            BLACK,
+            WHITE,
+            RED,
+            GREEN,
+            BLUE,
+            RGBLedColor::new(12, 200, 99),
+        ] {
+            assert_eq!(RGBLedColor::from_oklab(color.to_oklab()), color);
+        }
+        let white = WHITE.to_oklab();
+        assert!((white.l - 1.0).abs() < 1e-3);
+        assert!(white.a.abs() < 1e-3 && white.b.abs() < 1e-3);
+    }
+
+    #[test]
+    fn lerp_endpoints_and_midpoint() {
+        assert_eq!(RED.lerp(BLUE, 0.0), RED);
+        assert_eq!(RED.lerp(BLUE, 1.0), BLUE);
+        assert_eq!(RED.lerp(BLUE, -1.0), RED);
+        // half the light is brighter than half the byte value
+        assert_eq!(BLACK.lerp(WHITE, 0.5), RGBLedColor::new(188, 188, 188));
+    }
+
+    #[test]
+    fn mix_oklab_keeps_lightness() {
+        let middle = RED.mix_oklab(GREEN, 0.5).to_oklab();
+        let expected = (RED.to_oklab().l + GREEN.to_oklab().l) / 2.0;
+        assert!((middle.l - expected).abs() < 0.01);
+        assert_eq!(RED.mix_oklab(GREEN, 1.0), GREEN);
+    }
+
+    #[test]
+    fn over() {
+        assert_eq!(RED.over(BLUE, 1.0), RED);
+        assert_eq!(RED.over(BLUE, 0.0), BLUE);
+        assert_eq!(WHITE.over(BLACK, 0.5), BLACK.lerp(WHITE, 0.5));
+    }
+
+    #[test]
+    fn blend_modes() {
+        assert_eq!(RED.add(GREEN), RGBLedColor::new(255, 255, 0));
+        assert_eq!(WHITE.add(WHITE), WHITE);
+        assert_eq!(RED.multiply(WHITE), RED);
+        assert_eq!(RED.multiply(BLUE), BLACK);
+        assert_eq!(RED.screen(BLACK), RED);
+        assert_eq!(RED.screen(BLUE), RGBLedColor::new(255, 0, 255));
+        let grey = RGBLedColor::new(128, 128, 128);
+        assert!(grey.multiply(grey).red < grey.red);
+        assert!(grey.screen(grey).red > grey.red);
+    }
+}
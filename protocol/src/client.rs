@@ -0,0 +1,997 @@
+//! Blocking client for espled controllers, over any [`Transport`].
+//!
+//! A reader thread hands every reply to the request waiting for its ID and queues the [`Event`]s
+//! of the subscription. Requests are resent with the same ID when no reply arrives in time or the
+//! controller reports a frame it couldn't read.
+//!
+//! ```no_run
+//! use protocol::client::Client;
+//!
+//! # fn main() -> protocol::client::Result<()> {
+//! let mut client = Client::connect_tcp("192.168.1.50:7000")?;
+//! client.handshake()?;
+//! println!("{}: {:?}", client.get_name()?, client.get_effects()?);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    boxed::Box,
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt,
+    io::{self, BufReader, ErrorKind, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    string::String,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::Duration,
+    vec::Vec,
+};
+
+use crate::command::{self, Command};
+use crate::framing::{self, Encoding, FrameDecoder};
+use crate::{
+    Capability, DeviceInfo, EffectInfo, ErrorCode, Event, EventKind, ParameterDescriptor,
+    ParameterTypes, PowerState, RGBLedColor, Request, RequestFrame, Response, ResponseHeader,
+    Token, VersionInfo, ZoneId, ZoneInfo, PROTOCOL_VERSION,
+};
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(1000);
+pub const DEFAULT_TRIES: u32 = 5;
+/// How long transports opened here block on reads, the reader checks for a dropped client in between
+pub const READ_TIMEOUT: Duration = Duration::from_millis(100);
+pub const SERIAL_BAUD_RATE: u32 = 115200;
+
+static NEXT_REQUEST_ID: AtomicU32 = AtomicU32::new(1);
+/// `0` is never handed out as a request ID
+const NO_SUBSCRIPTION: u32 = 0;
+
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    /// No reply after every try
+    Timeout {
+        id: u32,
+        tries: u32,
+    },
+    /// The controller answered with an error
+    Controller(crate::Error),
+    /// The reply doesn't have the type of the request
+    InvalidReply(crate::Error),
+    /// The controller speaks another major protocol version
+    Incompatible(VersionInfo),
+    /// The request needs a capability the controller lacks
+    Unsupported(Capability),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(err) => write!(f, "{err}"),
+            ClientError::Timeout { id, tries } => {
+                write!(f, "no response to request {id} after {tries} tries")
+            }
+            ClientError::Controller(err) => write!(f, "{err}"),
+            ClientError::InvalidReply(err) => write!(f, "unexpected reply: {err}"),
+            ClientError::Incompatible(version) => write!(
+                f,
+                "incompatible protocol version {} (firmware {}), expected {}.x",
+                version.protocol_version, version.firmware_version, PROTOCOL_VERSION.major
+            ),
+            ClientError::Unsupported(capability) => {
+                write!(f, "controller does not support {capability:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Io(err) => Some(err),
+            ClientError::Controller(err) | ClientError::InvalidReply(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> Self {
+        ClientError::Io(err)
+    }
+}
+
+/// Byte stream to a controller. Reads have to time out now and then, with
+/// [`ErrorKind::TimedOut`] or [`ErrorKind::WouldBlock`], so the reader notices a dropped client
+pub trait Transport: Read + Write + Send + 'static {
+    /// Second handle to the same link, the client reads on one and writes on the other
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>>;
+}
+
+impl Transport for TcpStream {
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(TcpStream::try_clone(self)?))
+    }
+}
+
+#[cfg(feature = "serial")]
+impl Transport for Box<dyn serialport::SerialPort> {
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(serialport::SerialPort::try_clone(self.as_ref())?))
+    }
+}
+
+/// In-process link for tests and simulators, bytes written to one end of
+/// [`MemoryTransport::pair`] are read from the other
+#[derive(Clone)]
+pub struct MemoryTransport {
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+    read_timeout: Duration,
+}
+
+#[derive(Default)]
+struct Pipe {
+    bytes: Mutex<VecDeque<u8>>,
+    ready: Condvar,
+}
+
+impl MemoryTransport {
+    pub fn pair() -> (MemoryTransport, MemoryTransport) {
+        let (a, b) = (Arc::new(Pipe::default()), Arc::new(Pipe::default()));
+        (
+            MemoryTransport {
+                incoming: a.clone(),
+                outgoing: b.clone(),
+                read_timeout: READ_TIMEOUT,
+            },
+            MemoryTransport {
+                incoming: b,
+                outgoing: a,
+                read_timeout: READ_TIMEOUT,
+            },
+        )
+    }
+}
+
+impl Read for MemoryTransport {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let bytes = self.incoming.bytes.lock().unwrap();
+        let (mut bytes, _) = self
+            .incoming
+            .ready
+            .wait_timeout_while(bytes, self.read_timeout, |bytes| bytes.is_empty())
+            .unwrap();
+        if bytes.is_empty() {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        let length = buffer.len().min(bytes.len());
+        for (slot, byte) in buffer.iter_mut().zip(bytes.drain(..length)) {
+            *slot = byte;
+        }
+        Ok(length)
+    }
+}
+
+impl Write for MemoryTransport {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.outgoing.bytes.lock().unwrap().extend(buffer);
+        self.outgoing.ready.notify_all();
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(self.clone()))
+    }
+}
+
+/// What the reader hands to a waiting request
+enum Reply {
+    Frame(Encoding, Vec<u8>),
+    /// The controller dropped or rejected a frame that may have been ours
+    Resend,
+}
+
+type Pending = Arc<Mutex<HashMap<u32, Sender<Reply>>>>;
+
+pub struct Client {
+    link_name: String,
+    writer: Mutex<Box<dyn Transport>>,
+    pending: Pending,
+    /// ID of the subscribe request, events carry it
+    subscription: Arc<AtomicU32>,
+    events: Mutex<Receiver<Event>>,
+    closed: Arc<AtomicBool>,
+    version: Option<VersionInfo>,
+    /// Wraps requests in [`Request::Authorized`] when the controller supports pairing
+    token: Option<Token>,
+    encoding: Encoding,
+    timeout: Duration,
+    tries: u32,
+}
+
+impl Client {
+    /// Starts reading from `transport`, `link_name` identifies it in logs
+    pub fn new<T: Transport>(link_name: impl Into<String>, transport: T) -> io::Result<Self> {
+        let link_name = link_name.into();
+        let reader = transport.try_clone()?;
+
+        let pending: Pending = Arc::default();
+        let subscription = Arc::new(AtomicU32::new(NO_SUBSCRIPTION));
+        let closed = Arc::new(AtomicBool::new(false));
+        let (events_tx, events_rx) = mpsc::channel();
+
+        let reader_state = ReaderState {
+            link_name: link_name.clone(),
+            pending: pending.clone(),
+            subscription: subscription.clone(),
+            events: events_tx,
+            closed: closed.clone(),
+        };
+        thread::spawn(move || reader_state.run(reader));
+
+        Ok(Self {
+            link_name,
+            writer: Mutex::new(Box::new(transport)),
+            pending,
+            subscription,
+            events: Mutex::new(events_rx),
+            closed,
+            version: None,
+            token: None,
+            encoding: Encoding::Json,
+            timeout: DEFAULT_TIMEOUT,
+            tries: DEFAULT_TRIES,
+        })
+    }
+
+    #[cfg(feature = "serial")]
+    pub fn open_serial(port_name: &str) -> Result<Self> {
+        let port = serialport::new(port_name, SERIAL_BAUD_RATE)
+            .timeout(READ_TIMEOUT)
+            .open()
+            .map_err(io::Error::from)?;
+        Ok(Self::new(port_name, port)?)
+    }
+
+    pub fn connect_tcp<A: ToSocketAddrs + fmt::Display>(address: A) -> Result<Self> {
+        let stream = TcpStream::connect(&address)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(address.to_string(), stream)?)
+    }
+
+    /// How long to wait for each reply, [`DEFAULT_TIMEOUT`] by default
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How often a request is sent before giving up, [`DEFAULT_TRIES`] by default
+    pub fn with_tries(mut self, tries: u32) -> Self {
+        self.tries = tries.max(1);
+        self
+    }
+
+    /// Token from an earlier [`Client::pair`], needed on network links of controllers with
+    /// [`Capability::Pairing`]
+    pub fn with_token(mut self, token: Token) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    pub fn token(&self) -> Option<Token> {
+        self.token
+    }
+
+    pub fn link_name(&self) -> &str {
+        &self.link_name
+    }
+
+    /// Exchanges versions and switches to the best encoding both sides speak
+    pub fn handshake(&mut self) -> Result<&VersionInfo> {
+        // always JSON, so unknown capabilities of newer controllers can be skipped
+        let version = self.request_as(Encoding::Json, command::Hello)?;
+
+        if !version
+            .protocol_version
+            .is_compatible_with(&PROTOCOL_VERSION)
+        {
+            return Err(ClientError::Incompatible(version));
+        }
+        if version.protocol_version < PROTOCOL_VERSION {
+            log::warn!(
+                "{} runs older protocol {} (firmware {}), features it lacks will be disabled",
+                self.link_name,
+                version.protocol_version,
+                version.firmware_version
+            );
+        }
+
+        self.encoding = if version.supports(Capability::BinaryFraming) {
+            Encoding::Binary
+        } else if version.supports(Capability::FrameChecksum) {
+            Encoding::JsonChecksum
+        } else {
+            Encoding::Json
+        };
+        Ok(self.version.insert(version))
+    }
+
+    /// `None` until [`Client::handshake`]
+    pub fn version(&self) -> Option<&VersionInfo> {
+        self.version.as_ref()
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// `false` before [`Client::handshake`]
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.version
+            .as_ref()
+            .is_some_and(|version| version.supports(capability))
+    }
+
+    pub fn get_name(&self) -> Result<String> {
+        self.send(command::GetName)
+    }
+
+    pub fn get_effects(&self) -> Result<Vec<String>> {
+        self.send(command::GetEffects)
+    }
+
+    /// Name of the current effect
+    pub fn get_effect(&self) -> Result<String> {
+        self.send(command::GetEffect)
+    }
+
+    pub fn list_effects(&self) -> Result<Vec<EffectInfo>> {
+        self.send(command::ListEffects)
+    }
+
+    /// [`EffectInfo::id`] of the current effect
+    pub fn get_effect_id(&self) -> Result<String> {
+        self.send(command::GetEffectId)
+    }
+
+    pub fn get_parameters(&self) -> Result<Vec<ParameterDescriptor>> {
+        self.send(command::GetParameters)
+    }
+
+    pub fn get_device_info(&self) -> Result<DeviceInfo> {
+        self.send(command::GetDeviceInfo)
+    }
+
+    pub fn get_power_state(&self) -> Result<PowerState> {
+        self.send(command::GetPowerState)
+    }
+
+    /// Returns the new state
+    pub fn set_power(&self, on: bool) -> Result<bool> {
+        self.send(command::SetPower { on })
+    }
+
+    /// Returns the brightness actually applied, after clamping
+    pub fn set_brightness(&self, brightness: f32) -> Result<f32> {
+        self.send(command::SetBrightness { brightness })
+    }
+
+    pub fn set_effect(&self, index: usize) -> Result<()> {
+        self.send(command::SetEffect { index })
+    }
+
+    pub fn set_effect_by_id(&self, id: impl Into<String>) -> Result<()> {
+        self.send(command::SetEffectById { id: id.into() })
+    }
+
+    pub fn get_zones(&self) -> Result<Vec<ZoneInfo>> {
+        self.send(command::GetZones)
+    }
+
+    /// Returns the value actually applied, after clamping
+    pub fn set_option(
+        &self,
+        key: impl Into<String>,
+        value: ParameterTypes,
+    ) -> Result<ParameterTypes> {
+        self.send(command::SetOption {
+            key: key.into(),
+            value,
+        })
+    }
+
+    /// Sets every value or none, see [`Request::SetOptions`]
+    pub fn set_options(
+        &self,
+        values: BTreeMap<String, ParameterTypes>,
+    ) -> Result<BTreeMap<String, std::result::Result<ParameterTypes, crate::Error>>> {
+        self.send(command::SetOptions { values })
+    }
+
+    /// Asks the controller to show a pairing code, see [`pairing`](crate::pairing)
+    pub fn start_pairing(&self) -> Result<()> {
+        self.send(command::StartPairing)
+    }
+
+    /// Trades the code shown after [`Client::start_pairing`] for a token, later requests carry
+    /// it. Keep it for the next connection, see [`Client::with_token`]
+    pub fn pair(&mut self, code: u32) -> Result<Token> {
+        let token = self.send(command::Pair { code })?;
+        self.token = Some(token);
+        Ok(token)
+    }
+
+    /// Subscribes to `kinds`, an empty list ends the subscription. Events are read with
+    /// [`Client::events`]
+    pub fn subscribe(&self, kinds: Vec<EventKind>) -> Result<()> {
+        let command = command::Subscribe { kinds };
+        self.require_for(&command)?;
+        let unsubscribe = command.kinds.is_empty();
+        let id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+        // the reply is routed to the request first, only later frames with this ID are events
+        self.subscription.store(id, Ordering::Relaxed);
+        let subscribed = self.request_with_id(id, self.encoding, command)?;
+        if unsubscribe {
+            self.subscription.store(NO_SUBSCRIPTION, Ordering::Relaxed);
+        }
+        log::debug!("{}: {subscribed:?}", self.link_name);
+        Ok(())
+    }
+
+    /// Events received since the last call
+    pub fn events(&self) -> Vec<Event> {
+        self.events.lock().unwrap().try_iter().collect()
+    }
+
+    /// Shows `color` until no other one arrives for `timeout`. Not answered, so not retried either
+    pub fn stream_color(&self, color: RGBLedColor, timeout: Duration) -> Result<()> {
+        self.require(Capability::ColorStreaming)?;
+        self.write_unanswered(Self::color_stream(color, timeout))
+    }
+
+    /// Like [`Client::stream_color`], for one zone
+    pub fn stream_color_in_zone(
+        &self,
+        zone: ZoneId,
+        color: RGBLedColor,
+        timeout: Duration,
+    ) -> Result<()> {
+        self.require(Capability::ColorStreaming)?;
+        self.require(Capability::Zones)?;
+        let request = Request::InZone(zone, Box::new(Self::color_stream(color, timeout)));
+        self.write_unanswered(request)
+    }
+
+    /// Sends any command and waits for its reply. Fails without sending anything when the
+    /// controller lacks [`Command::CAPABILITY`]
+    pub fn send<C: Command>(&self, command: C) -> Result<C::Response> {
+        self.require_for(&command)?;
+        self.request_as(self.encoding, command)
+    }
+
+    fn request_as<C: Command>(&self, encoding: Encoding, command: C) -> Result<C::Response> {
+        let id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+        self.request_with_id(id, encoding, command)
+    }
+
+    fn require(&self, capability: Capability) -> Result<()> {
+        if self.supports(capability) {
+            Ok(())
+        } else {
+            Err(ClientError::Unsupported(capability))
+        }
+    }
+
+    fn require_for<C: Command>(&self, _: &C) -> Result<()> {
+        C::CAPABILITY.map_or(Ok(()), |capability| self.require(capability))
+    }
+
+    fn color_stream(color: RGBLedColor, timeout: Duration) -> Request {
+        Request::StreamColor {
+            color,
+            timeout_ms: timeout.as_millis().try_into().unwrap_or(u32::MAX),
+        }
+    }
+
+    /// Wraps `request` in the token, if there is one and the controller asks for it
+    fn authorize(&self, request: Request) -> Request {
+        match self.token {
+            Some(token)
+                if self.supports(Capability::Pairing) && request.needs_token() =>
+            {
+                Request::Authorized(token, Box::new(request))
+            }
+            _ => request,
+        }
+    }
+
+    fn write_unanswered(&self, request: Request) -> Result<()> {
+        let id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+        let request = self.authorize(request);
+        let frame = framing::encode(self.encoding, &RequestFrame { id, request });
+        self.write_frame(&frame)?;
+        Ok(())
+    }
+
+    fn write_frame(&self, frame: &[u8]) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(frame)?;
+        writer.flush()
+    }
+
+    fn request_with_id<C: Command>(
+        &self,
+        id: u32,
+        encoding: Encoding,
+        command: C,
+    ) -> Result<C::Response> {
+        let request: Request = command.into();
+        // the controller takes a resent code as another guess and a resent StartPairing as
+        // pairing in progress, so these go out once
+        let tries = match request {
+            Request::StartPairing | Request::Pair(_) => 1,
+            _ => self.tries,
+        };
+        let request = self.authorize(request);
+        log::debug!("← {request:?} (id: {id}, {encoding:?})");
+        let request_frame = framing::encode(encoding, &RequestFrame { id, request });
+
+        for _ in 0..tries {
+            let (reply_tx, reply_rx) = mpsc::channel();
+            self.pending.lock().unwrap().insert(id, reply_tx);
+            if let Err(err) = self.write_frame(&request_frame) {
+                self.pending.lock().unwrap().remove(&id);
+                return Err(err.into());
+            }
+
+            match reply_rx.recv_timeout(self.timeout) {
+                Ok(Reply::Frame(encoding, frame)) => {
+                    let response: Response<C::Response> =
+                        framing::decode(encoding, &frame).map_err(ClientError::InvalidReply)?;
+                    return response.result.map_err(ClientError::Controller);
+                }
+                Ok(Reply::Resend) => {}
+                Err(_) => log::warn!("no reply to request {id} from {}", self.link_name),
+            }
+        }
+        self.pending.lock().unwrap().remove(&id);
+
+        Err(ClientError::Timeout { id, tries })
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("link_name", &self.link_name)
+            .field("encoding", &self.encoding)
+            .finish_non_exhaustive()
+    }
+}
+
+struct ReaderState {
+    link_name: String,
+    pending: Pending,
+    subscription: Arc<AtomicU32>,
+    events: Sender<Event>,
+    closed: Arc<AtomicBool>,
+}
+
+impl ReaderState {
+    fn run(self, transport: Box<dyn Transport>) {
+        let mut reader = BufReader::new(transport);
+        let mut decoder = FrameDecoder::new();
+        let mut byte = [0u8; 1];
+
+        while !self.closed.load(Ordering::Relaxed) {
+            match reader.read_exact(&mut byte) {
+                Ok(()) => {
+                    if let Some((encoding, frame)) = decoder.push(byte[0]) {
+                        self.dispatch(encoding, frame);
+                    }
+                }
+                Err(err) if matches!(err.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {}
+                Err(err) => {
+                    log::warn!("failed to read from: {} error: {}", self.link_name, err);
+                    break;
+                }
+            }
+        }
+        log::debug!("reader of {} stopped", self.link_name);
+    }
+
+    fn dispatch(&self, encoding: Encoding, frame: Vec<u8>) {
+        match encoding {
+            Encoding::Json | Encoding::JsonChecksum => {
+                log::debug!("→ {}: {}", self.link_name, String::from_utf8_lossy(&frame))
+            }
+            Encoding::Binary => {
+                log::debug!("→ {}: {} bytes", self.link_name, frame.len())
+            }
+        }
+
+        match framing::decode::<ResponseHeader>(encoding, &frame) {
+            Ok(ResponseHeader { id: Some(id) }) => {
+                if let Some(reply) = self.pending.lock().unwrap().remove(&id) {
+                    let _ = reply.send(Reply::Frame(encoding, frame));
+                } else if id == self.subscription.load(Ordering::Relaxed) {
+                    match framing::decode::<Response<Event>>(encoding, &frame) {
+                        Ok(Response {
+                            result: Ok(event), ..
+                        }) => {
+                            let _ = self.events.send(event);
+                        }
+                        Ok(Response {
+                            result: Err(err), ..
+                        }) => {
+                            log::warn!("{} sent an error event: {err}", self.link_name)
+                        }
+                        Err(err) => log::warn!("invalid event from {}: {err}", self.link_name),
+                    }
+                } else {
+                    log::debug!("skipping reply to request {id}");
+                }
+            }
+            Ok(ResponseHeader { id: None }) => {
+                // the controller couldn't read a request, it may have been any of the pending ones
+                if let Ok(Response {
+                    result: Err(err), ..
+                }) = framing::decode::<Response<()>>(encoding, &frame)
+                {
+                    log::warn!("{} rejected a request: {err}", self.link_name);
+                }
+                self.resend_pending();
+            }
+            Err(err) if err.code == ErrorCode::CorruptFrame => {
+                log::warn!("dropped frame from: {}: {err}", self.link_name);
+                self.resend_pending();
+            }
+            Err(_) => {
+                log::warn!(
+                    "invalid response from: {}, got: {}",
+                    self.link_name,
+                    String::from_utf8_lossy(&frame)
+                );
+            }
+        }
+    }
+
+    fn resend_pending(&self) {
+        for (_, reply) in self.pending.lock().unwrap().drain() {
+            let _ = reply.send(Reply::Resend);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Error, ProtocolVersion, DEFAULT_ZONE};
+    use serde::Serialize;
+    use std::time::Instant;
+
+    const SHORT_TIMEOUT: Duration = Duration::from_millis(50);
+    const PAIRING_CODE: u32 = 123456;
+    const TOKEN: Token = Token([5; 16]);
+
+    /// Controller with the effects "Direct" and "Rainbow" and a "speed" parameter in [0.0, 1.0]
+    struct FakeController {
+        protocol_version: ProtocolVersion,
+        capabilities: Vec<Capability>,
+        /// Requests to ignore before answering, the handshake is always answered
+        ignore: usize,
+        /// Like a network link, requests have to carry it. Issued for [`PAIRING_CODE`]
+        token: Option<Token>,
+    }
+
+    impl FakeController {
+        fn new(capabilities: &[Capability]) -> Self {
+            Self {
+                protocol_version: PROTOCOL_VERSION,
+                capabilities: capabilities.to_vec(),
+                ignore: 0,
+                token: None,
+            }
+        }
+
+        fn connect(self) -> Client {
+            let (client, controller) = MemoryTransport::pair();
+            thread::spawn(move || self.run(controller));
+            Client::new("fake", client).unwrap()
+        }
+
+        fn run(mut self, mut transport: MemoryTransport) {
+            let mut decoder = FrameDecoder::new();
+            let mut buffer = [0u8; 64];
+            loop {
+                let length = match transport.read(&mut buffer) {
+                    Ok(length) => length,
+                    Err(err) if err.kind() == ErrorKind::TimedOut => continue,
+                    Err(err) => panic!("{err}"),
+                };
+                for byte in &buffer[..length] {
+                    if let Some((encoding, frame)) = decoder.push(*byte) {
+                        let frame: RequestFrame = framing::decode(encoding, &frame).unwrap();
+                        if self.ignore > 0 && !matches!(frame.request, Request::Hello) {
+                            self.ignore -= 1;
+                            continue;
+                        }
+                        if self.token.is_some() && frame.request.needs_token() {
+                            let err = Error::new(ErrorCode::Unauthorized, "not paired");
+                            reply::<()>(&mut transport, encoding, frame.id, Err(err));
+                            continue;
+                        }
+                        self.handle(&mut transport, encoding, frame);
+                    }
+                }
+            }
+        }
+
+        fn handle(&self, transport: &mut MemoryTransport, encoding: Encoding, frame: RequestFrame) {
+            let id = frame.id;
+            match frame.request {
+                Request::Hello => reply(
+                    transport,
+                    encoding,
+                    id,
+                    Ok(VersionInfo {
+                        protocol_version: self.protocol_version,
+                        firmware_version: "test".into(),
+                        capabilities: self.capabilities.clone(),
+                    }),
+                ),
+                Request::GetName => reply(transport, encoding, id, Ok("fake")),
+                Request::GetEffects => {
+                    reply(transport, encoding, id, Ok(vec!["Direct", "Rainbow"]))
+                }
+                Request::SetEffect(index) => {
+                    let result = match index {
+                        0 | 1 => Ok(()),
+                        _ => Err(Error::new(ErrorCode::UnknownEffect, "no such effect")),
+                    };
+                    reply(transport, encoding, id, result)
+                }
+                Request::SetOption(key, ParameterTypes::Float(value)) if key == "speed" => {
+                    let value = ParameterTypes::Float(value.clamp(0.0, 1.0));
+                    reply(transport, encoding, id, Ok(value))
+                }
+                Request::InZone(DEFAULT_ZONE, request) => {
+                    let request = *request;
+                    self.handle(transport, encoding, RequestFrame { id, request })
+                }
+                Request::InZone(..) => {
+                    let err = Error::new(ErrorCode::UnknownZone, "no such zone");
+                    reply::<()>(transport, encoding, id, Err(err))
+                }
+                Request::StartPairing => reply(transport, encoding, id, Ok(())),
+                Request::Pair(PAIRING_CODE) if self.token.is_some() => {
+                    reply::<Token>(transport, encoding, id, Ok(self.token.unwrap()))
+                }
+                Request::Authorized(token, request) if Some(token) == self.token => {
+                    let request = *request;
+                    self.handle(transport, encoding, RequestFrame { id, request })
+                }
+                Request::Pair(_) | Request::Authorized(..) => {
+                    let err = Error::new(ErrorCode::Unauthorized, "wrong code or token");
+                    reply::<()>(transport, encoding, id, Err(err))
+                }
+                Request::Subscribe(kinds) => {
+                    reply(transport, encoding, id, Ok(Event::Subscribed(kinds)));
+                    reply(transport, encoding, id, Ok(Event::PowerChanged(false)));
+                }
+                _ => {
+                    let err = Error::new(ErrorCode::UnknownParameter, "unsupported");
+                    reply::<()>(transport, encoding, id, Err(err))
+                }
+            }
+        }
+    }
+
+    fn reply<T: Serialize>(
+        transport: &mut MemoryTransport,
+        encoding: Encoding,
+        id: u32,
+        result: std::result::Result<T, Error>,
+    ) {
+        let frame = framing::encode(encoding, &Response::new(Some(id), result));
+        transport.write_all(&frame).unwrap();
+    }
+
+    #[test]
+    fn typed_requests() {
+        let mut client =
+            FakeController::new(&[Capability::BinaryFraming, Capability::FrameChecksum]).connect();
+        client.handshake().unwrap();
+        assert_eq!(client.encoding(), Encoding::Binary);
+
+        assert_eq!(client.get_name().unwrap(), "fake");
+        assert_eq!(
+            client.send(command::GetEffects).unwrap(),
+            ["Direct", "Rainbow"]
+        );
+        assert_eq!(client.get_effects().unwrap(), ["Direct", "Rainbow"]);
+        client.set_effect(1).unwrap();
+        match client.set_effect(2) {
+            Err(ClientError::Controller(err)) => assert_eq!(err.code, ErrorCode::UnknownEffect),
+            other => panic!("{other:?}"),
+        }
+        assert_eq!(
+            client
+                .set_option("speed", ParameterTypes::Float(2.0))
+                .unwrap(),
+            ParameterTypes::Float(1.0)
+        );
+    }
+
+    #[test]
+    fn best_encoding_is_negotiated() {
+        for (capabilities, encoding) in [
+            (
+                &[Capability::BinaryFraming, Capability::FrameChecksum][..],
+                Encoding::Binary,
+            ),
+            (&[Capability::BinaryFraming], Encoding::Binary),
+            (&[Capability::FrameChecksum], Encoding::JsonChecksum),
+            (&[], Encoding::Json),
+        ] {
+            let mut client = FakeController::new(capabilities).connect();
+            client.handshake().unwrap();
+            assert_eq!(client.encoding(), encoding, "{capabilities:?}");
+            assert_eq!(client.get_name().unwrap(), "fake");
+        }
+    }
+
+    #[test]
+    fn old_controllers_stay_on_json() {
+        let mut client = FakeController::new(&[]).connect();
+        client.handshake().unwrap();
+        assert_eq!(client.encoding(), Encoding::Json);
+        assert!(matches!(
+            client.set_options(BTreeMap::new()),
+            Err(ClientError::Unsupported(Capability::BatchParameters))
+        ));
+        assert!(matches!(
+            client.send(command::GetPowerState),
+            Err(ClientError::Unsupported(Capability::PowerControl))
+        ));
+    }
+
+    #[test]
+    fn zones_wrap_requests() {
+        let mut client = FakeController::new(&[Capability::Zones]).connect();
+        client.handshake().unwrap();
+
+        let in_zone = |zone| command::InZone {
+            zone,
+            command: command::SetEffect { index: 1 },
+        };
+        client.send(in_zone(DEFAULT_ZONE)).unwrap();
+        match client.send(in_zone(1)) {
+            Err(ClientError::Controller(err)) => assert_eq!(err.code, ErrorCode::UnknownZone),
+            other => panic!("{other:?}"),
+        }
+    }
+
+    #[test]
+    fn paired_clients_authorize_requests() {
+        let unauthorized = |result: Result<_>| match result {
+            Err(ClientError::Controller(err)) => assert_eq!(err.code, ErrorCode::Unauthorized),
+            other => panic!("{other:?}"),
+        };
+        for (capabilities, encoding) in [
+            (&[Capability::Pairing][..], Encoding::Json),
+            (
+                &[Capability::Pairing, Capability::BinaryFraming],
+                Encoding::Binary,
+            ),
+        ] {
+            let connect = || {
+                let mut controller = FakeController::new(capabilities);
+                controller.token = Some(TOKEN);
+                controller.connect()
+            };
+
+            let mut client = connect();
+            client.handshake().unwrap();
+            assert_eq!(client.encoding(), encoding);
+            unauthorized(client.get_name().map(drop));
+            client.start_pairing().unwrap();
+            unauthorized(client.pair(PAIRING_CODE + 1).map(drop));
+            assert_eq!(client.token(), None);
+            assert_eq!(client.pair(PAIRING_CODE).unwrap(), TOKEN);
+            assert_eq!(client.get_name().unwrap(), "fake");
+
+            let mut client = connect().with_token(TOKEN);
+            client.handshake().unwrap();
+            assert_eq!(client.get_name().unwrap(), "fake");
+            let mut client = connect().with_token(Token([6; 16]));
+            client.handshake().unwrap();
+            unauthorized(client.get_name().map(drop));
+        }
+    }
+
+    #[test]
+    fn incompatible_version_is_rejected() {
+        let mut controller = FakeController::new(&[]);
+        controller.protocol_version.major += 1;
+        let mut client = controller.connect();
+        assert!(matches!(
+            client.handshake(),
+            Err(ClientError::Incompatible(_))
+        ));
+    }
+
+    #[test]
+    fn lost_requests_are_resent() {
+        let mut controller = FakeController::new(&[]);
+        controller.ignore = 2;
+        let client = controller.connect().with_timeout(SHORT_TIMEOUT);
+        assert_eq!(client.get_name().unwrap(), "fake");
+
+        let mut controller = FakeController::new(&[]);
+        controller.ignore = usize::MAX;
+        let client = controller
+            .connect()
+            .with_timeout(SHORT_TIMEOUT)
+            .with_tries(2);
+        assert!(matches!(
+            client.get_name(),
+            Err(ClientError::Timeout { tries: 2, .. })
+        ));
+
+        // answered the second time, but pairing requests go out once
+        let connect = || {
+            let mut controller = FakeController::new(&[Capability::Pairing]);
+            controller.ignore = 1;
+            let mut client = controller
+                .connect()
+                .with_timeout(SHORT_TIMEOUT)
+                .with_tries(3);
+            client.handshake().unwrap();
+            client
+        };
+        assert!(matches!(
+            connect().start_pairing(),
+            Err(ClientError::Timeout { tries: 1, .. })
+        ));
+        assert!(matches!(
+            connect().pair(PAIRING_CODE),
+            Err(ClientError::Timeout { tries: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn events_are_queued() {
+        let mut client = FakeController::new(&[Capability::Events]).connect();
+        client.handshake().unwrap();
+        client.subscribe(EventKind::ALL.to_vec()).unwrap();
+
+        let deadline = Instant::now() + DEFAULT_TIMEOUT;
+        let mut events = Vec::new();
+        while events.is_empty() && Instant::now() < deadline {
+            events = client.events();
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(events, [Event::PowerChanged(false)]);
+    }
+}
@@ -0,0 +1,557 @@
+use core::{fmt, str::FromStr};
+
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+use serde::{Deserialize, Serialize};
+
+use crate::css_colors::NAMED_COLORS;
+
+/// Serialised and read as a struct. Use [`hex`] to write colours as `#rrggbb` and to read any
+/// form of [`RGBLedColor::from_str`]
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize)]
+pub struct RGBLedColor {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+/// Wraps a hue in degrees into [0.0, 360.0). NaN and infinities become 0.0
+pub fn normalize_hue(hue: f32) -> f32 {
+    if !hue.is_finite() {
+        return 0.0;
+    }
+    let hue = rem_euclid(hue, 360.0);
+    // rem_euclid rounds tiny negative values up to 360.0
+    if hue >= 360.0 {
+        0.0
+    } else {
+        hue
+    }
+}
+
+/// `f32::rem_euclid`, which is not in `core`
+fn rem_euclid(value: f32, modulus: f32) -> f32 {
+    let remainder = value % modulus;
+    if remainder < 0.0 {
+        remainder + modulus
+    } else {
+        remainder
+    }
+}
+
+/// Coldest and warmest temperature [`RGBLedColor::from_kelvin`] is defined for
+pub const MIN_KELVIN: u16 = 1000;
+pub const MAX_KELVIN: u16 = 40000;
+
+impl RGBLedColor {
+    pub const fn new(red: u8, green: u8, blue: u8) -> Self {
+        Self { red, green, blue }
+    }
+
+    /// Colour of a black body at `kelvin`, clamped to [`MIN_KELVIN`]..=[`MAX_KELVIN`].
+    /// Uses Tanner Helland's fit of the CIE 1964 colour matching data, 6600K is white
+    pub fn from_kelvin(kelvin: u16) -> Self {
+        let temperature = kelvin.clamp(MIN_KELVIN, MAX_KELVIN) as f32 / 100.0;
+
+        let red = if temperature <= 66.0 {
+            255.0
+        } else {
+            329.69873 * libm::powf(temperature - 60.0, -0.13320476)
+        };
+        let green = if temperature <= 66.0 {
+            99.4708 * libm::logf(temperature) - 161.11957
+        } else {
+            288.12216 * libm::powf(temperature - 60.0, -0.075514846)
+        };
+        let blue = if temperature >= 66.0 {
+            255.0
+        } else if temperature <= 19.0 {
+            0.0
+        } else {
+            138.51773 * libm::logf(temperature - 10.0) - 305.0448
+        };
+
+        let channel = |value: f32| libm::roundf(value).clamp(0.0, 255.0) as u8;
+        Self::new(channel(red), channel(green), channel(blue))
+    }
+
+    /// H - any value, wrapped with [`normalize_hue`], S - [0.0, 1.0], V - [0.0, 1.0]
+    pub fn from_hsv(h: f32, s: f32, v: f32) -> Self {
+        let s = s.clamp(0.0, 1.0);
+        let v = v.clamp(0.0, 1.0);
+        let c = v * s; // chroma
+        Self::from_hue_chroma(h, c, v - c)
+    }
+
+    /// H - any value, wrapped with [`normalize_hue`], S - [0.0, 1.0], L - [0.0, 1.0]
+    pub fn from_hsl(h: f32, s: f32, l: f32) -> Self {
+        let s = s.clamp(0.0, 1.0);
+        let l = l.clamp(0.0, 1.0);
+        let c = (1.0 - (2.0 * l - 1.0).abs()) * s; // chroma
+        Self::from_hue_chroma(h, c, l - c / 2.0)
+    }
+
+    /// Inverse of [`RGBLedColor::from_hsv`], returns (H, S, V). Greys have a hue of 0.0
+    pub fn to_hsv(&self) -> (f32, f32, f32) {
+        let (h, max, c) = self.hue_chroma();
+        let s = if max > 0.0 { c / max } else { 0.0 };
+        (h, s, max)
+    }
+
+    /// Inverse of [`RGBLedColor::from_hsl`], returns (H, S, L). Greys have a hue of 0.0
+    pub fn to_hsl(&self) -> (f32, f32, f32) {
+        let (h, max, c) = self.hue_chroma();
+        let l = max - c / 2.0;
+        let s = if l > 0.0 && l < 1.0 {
+            c / (1.0 - (2.0 * l - 1.0).abs())
+        } else {
+            0.0
+        };
+        (h, s.min(1.0), l)
+    }
+
+    /// Shared tail of the HSV and HSL conversions, `m` is added to every channel
+    fn from_hue_chroma(h: f32, c: f32, m: f32) -> Self {
+        let sector = normalize_hue(h) / 60.0;
+        let x = c * (1.0 - (sector % 2.0 - 1.0).abs());
+
+        let (r1, g1, b1) = match sector as u8 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+
+        let channel = |value: f32| libm::roundf((value + m) * 255.0).clamp(0.0, 255.0) as u8;
+        Self::new(channel(r1), channel(g1), channel(b1))
+    }
+
+    /// Hue, largest channel and chroma, with the channels scaled to [0.0, 1.0]
+    fn hue_chroma(&self) -> (f32, f32, f32) {
+        let [red, green, blue]: [f32; 3] = (*self).into();
+        let max = red.max(green).max(blue);
+        let min = red.min(green).min(blue);
+        let c = max - min;
+
+        let h = if c == 0.0 {
+            0.0
+        } else if max == red {
+            60.0 * rem_euclid((green - blue) / c, 6.0)
+        } else if max == green {
+            60.0 * ((blue - red) / c + 2.0)
+        } else {
+            60.0 * ((red - green) / c + 4.0)
+        };
+        (normalize_hue(h), max, c)
+    }
+
+    /// Multiplies every channel by `factor`, clamped to [0.0, 1.0]
+    pub fn scale(&self, factor: f32) -> Self {
+        let factor = factor.clamp(0.0, 1.0);
+        let channel = |value: u8| libm::roundf(value as f32 * factor) as u8;
+        Self::new(channel(self.red), channel(self.green), channel(self.blue))
+    }
+
+    pub fn new_from_u32(color: u32) -> Self {
+        Self {
+            red: ((color >> 16) & 0xFF) as u8,
+            green: ((color >> 8) & 0xFF) as u8,
+            blue: (color & 0xFF) as u8,
+        }
+    }
+
+    pub fn gamma_correct(&mut self, coeficient: f32) {
+        let channel =
+            |value: u8| libm::roundf(libm::powf(value as f32 / 255.0, coeficient) * 255.0) as u8;
+        self.red = channel(self.red);
+        self.green = channel(self.green);
+        self.blue = channel(self.blue);
+    }
+
+    pub fn to_u32(&self) -> u32 {
+        let red = (self.red as u32) << 16;
+        let green = (self.green as u32) << 8;
+        let blue = self.blue as u32;
+
+        red | green | blue
+    }
+}
+
+impl From<RGBLedColor> for [f32; 3] {
+    fn from(color: RGBLedColor) -> Self {
+        [
+            color.red as f32 / 255.0,
+            color.green as f32 / 255.0,
+            color.blue as f32 / 255.0,
+        ]
+    }
+}
+
+impl From<[f32; 3]> for RGBLedColor {
+    fn from(arr: [f32; 3]) -> Self {
+        RGBLedColor {
+            red: libm::roundf(arr[0] * 255.0) as u8,
+            green: libm::roundf(arr[1] * 255.0) as u8,
+            blue: libm::roundf(arr[2] * 255.0) as u8,
+        }
+    }
+}
+
+impl PartialEq for RGBLedColor {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_u32() == other.to_u32()
+    }
+}
+
+impl Eq for RGBLedColor {}
+
+/// Lower case `#rrggbb`
+impl fmt::Display for RGBLedColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:06x}", self.to_u32())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseColorError {
+    #[cfg(feature = "alloc")]
+    input: String,
+}
+
+impl fmt::Display for ParseColorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid colour")?;
+        #[cfg(feature = "alloc")]
+        write!(f, " {:?}", self.input)?;
+        f.write_str(", expected #RRGGBB, #RGB, rgb(r, g, b) or a CSS colour name")
+    }
+}
+
+impl core::error::Error for ParseColorError {}
+
+/// Parses `#RRGGBB`, `#RGB`, `rgb(r, g, b)` with channels in 0..=255 and the CSS named colours,
+/// ignoring case and surrounding whitespace
+impl FromStr for RGBLedColor {
+    type Err = ParseColorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseColorError {
+            #[cfg(feature = "alloc")]
+            input: s.into(),
+        };
+        let text = s.trim();
+
+        if let Some(hex) = text.strip_prefix('#') {
+            if !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+                return Err(error());
+            }
+            let value = u32::from_str_radix(hex, 16).map_err(|_| error())?;
+            return match hex.len() {
+                6 => Ok(Self::new_from_u32(value)),
+                // every digit is doubled, #f80 is #ff8800
+                3 => {
+                    let digit = |shift: u32| ((value >> shift) & 0xF) as u8 * 0x11;
+                    Ok(Self::new(digit(8), digit(4), digit(0)))
+                }
+                _ => Err(error()),
+            };
+        }
+
+        const RGB_PREFIX: &str = "rgb(";
+        if let Some(channels) = text
+            .get(..RGB_PREFIX.len())
+            .filter(|prefix| prefix.eq_ignore_ascii_case(RGB_PREFIX))
+            .and_then(|_| text[RGB_PREFIX.len()..].strip_suffix(')'))
+        {
+            let mut channels = channels
+                .split(',')
+                .map(|channel| channel.trim().parse::<u8>());
+            return match (
+                channels.next(),
+                channels.next(),
+                channels.next(),
+                channels.next(),
+            ) {
+                (Some(Ok(red)), Some(Ok(green)), Some(Ok(blue)), None) => {
+                    Ok(Self::new(red, green, blue))
+                }
+                _ => Err(error()),
+            };
+        }
+
+        // the table is lower case, compare without allocating a lower case copy
+        NAMED_COLORS
+            .binary_search_by(|(name, _)| {
+                name.bytes()
+                    .cmp(text.bytes().map(|byte| byte.to_ascii_lowercase()))
+            })
+            .map(|index| Self::new_from_u32(NAMED_COLORS[index].1))
+            .map_err(|_| error())
+    }
+}
+
+/// The struct form, or with [`Capability::ColorStrings`](crate::Capability::ColorStrings) any text
+/// form. Controllers always send the struct form
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for RGBLedColor {
+    fn schema_name() -> alloc::borrow::Cow<'static, str> {
+        "RGBLedColor".into()
+    }
+
+    fn json_schema(generator: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        let channel = generator.subschema_for::<u8>();
+        schemars::json_schema!({
+            "anyOf": [
+                {
+                    "type": "object",
+                    "properties": {
+                        "red": channel,
+                        "green": channel,
+                        "blue": channel,
+                    },
+                    "required": ["red", "green", "blue"],
+                },
+                {
+                    "type": "string",
+                    "description": "#RRGGBB, #RGB, rgb(r, g, b) or a CSS colour name",
+                },
+            ],
+        })
+    }
+}
+
+/// Opt-in `#rrggbb` representation: `#[serde(with = "protocol::color::hex")]`.
+/// Deserialisation still takes the struct form, and with `alloc` any text form of
+/// [`RGBLedColor::from_str`]. The colour fields of requests read colours with it, see
+/// [`Capability::ColorStrings`](crate::Capability::ColorStrings)
+pub mod hex {
+    #[cfg(feature = "alloc")]
+    use alloc::string::String;
+    #[cfg(feature = "alloc")]
+    use serde::de;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::RGBLedColor;
+
+    pub fn serialize<S: Serializer>(color: &RGBLedColor, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(color)
+        } else {
+            color.serialize(serializer)
+        }
+    }
+
+    /// Text forms need `alloc`, the string has to be buffered to tell it from a struct
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<RGBLedColor, D::Error> {
+        #[cfg(feature = "alloc")]
+        if deserializer.is_human_readable() {
+            #[derive(Deserialize)]
+            #[serde(untagged)]
+            enum HumanReadable {
+                Text(String),
+                Fields(RGBLedColor),
+            }
+
+            return match HumanReadable::deserialize(deserializer)? {
+                HumanReadable::Text(text) => text.parse().map_err(de::Error::custom),
+                HumanReadable::Fields(color) => Ok(color),
+            };
+        }
+        RGBLedColor::deserialize(deserializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use proptest::prelude::*;
+
+    fn any_color() -> impl Strategy<Value = RGBLedColor> {
+        any::<(u8, u8, u8)>().prop_map(|(red, green, blue)| RGBLedColor::new(red, green, blue))
+    }
+
+    #[test]
+    fn hue_boundaries() {
+        assert_eq!(
+            RGBLedColor::from_hsv(0.0, 1.0, 1.0),
+            RGBLedColor::new(255, 0, 0)
+        );
+        assert_eq!(
+            RGBLedColor::from_hsv(60.0, 1.0, 1.0),
+            RGBLedColor::new(255, 255, 0)
+        );
+        assert_eq!(
+            RGBLedColor::from_hsv(120.0, 1.0, 1.0),
+            RGBLedColor::new(0, 255, 0)
+        );
+        assert_eq!(
+            RGBLedColor::from_hsv(240.0, 1.0, 1.0),
+            RGBLedColor::new(0, 0, 255)
+        );
+        assert_eq!(
+            RGBLedColor::from_hsv(360.0, 1.0, 1.0),
+            RGBLedColor::new(255, 0, 0)
+        );
+        assert_eq!(
+            RGBLedColor::from_hsv(-120.0, 1.0, 1.0),
+            RGBLedColor::new(0, 0, 255)
+        );
+        assert_eq!(
+            RGBLedColor::from_hsl(480.0, 1.0, 0.5),
+            RGBLedColor::new(0, 255, 0)
+        );
+    }
+
+    #[test]
+    fn kelvin_endpoints() {
+        assert_eq!(
+            RGBLedColor::from_kelvin(6600),
+            RGBLedColor::new(255, 255, 255)
+        );
+        assert_eq!(
+            RGBLedColor::from_kelvin(0),
+            RGBLedColor::from_kelvin(MIN_KELVIN)
+        );
+        assert_eq!(
+            RGBLedColor::from_kelvin(u16::MAX),
+            RGBLedColor::from_kelvin(MAX_KELVIN)
+        );
+        let warm = RGBLedColor::from_kelvin(2700);
+        assert!(warm.red > warm.green && warm.green > warm.blue);
+        let cold = RGBLedColor::from_kelvin(20000);
+        assert!(cold.blue > cold.green && cold.green > cold.red);
+    }
+
+    #[test]
+    fn parse() {
+        let orange = RGBLedColor::new(0xff, 0x88, 0x00);
+        assert_eq!("#ff8800".parse(), Ok(orange));
+        assert_eq!(" #FF8800 ".parse(), Ok(orange));
+        assert_eq!("#f80".parse(), Ok(orange));
+        assert_eq!("rgb(255, 136, 0)".parse(), Ok(orange));
+        assert_eq!("RGB(255,136,0)".parse(), Ok(orange));
+        assert_eq!("DarkOrange".parse(), Ok(RGBLedColor::new(0xff, 0x8c, 0x00)));
+        assert_eq!(
+            "rebeccapurple".parse(),
+            Ok(RGBLedColor::new(0x66, 0x33, 0x99))
+        );
+
+        for invalid in [
+            "",
+            "#",
+            "#ff880",
+            "#ff88001",
+            "#+f8800",
+            "#gg8800",
+            "rgb(256, 0, 0)",
+            "rgb(1, 2)",
+            "rgb(1, 2, 3, 4)",
+            "ff8800",
+            "notacolor",
+        ] {
+            assert!(
+                invalid.parse::<RGBLedColor>().is_err(),
+                "{invalid:?} parsed"
+            );
+        }
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(RGBLedColor::new(0xff, 0x88, 0x00).to_string(), "#ff8800");
+        assert_eq!(RGBLedColor::new(0, 0, 1).to_string(), "#000001");
+    }
+
+    #[test]
+    fn serde_forms() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Hex(#[serde(with = "hex")] RGBLedColor);
+
+        let orange = RGBLedColor::new(0xff, 0x88, 0x00);
+        assert_eq!(
+            serde_json::to_string(&orange).unwrap(),
+            r#"{"red":255,"green":136,"blue":0}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Hex(orange)).unwrap(),
+            r##""#ff8800""##
+        );
+
+        let json = r#"{"red":255,"green":136,"blue":0}"#;
+        assert_eq!(serde_json::from_str::<RGBLedColor>(json).unwrap(), orange);
+        for json in [json, r##""#ff8800""##, r#""rgb(255, 136, 0)""#] {
+            assert_eq!(serde_json::from_str::<Hex>(json).unwrap(), Hex(orange));
+        }
+        // only opted in fields take strings
+        assert!(serde_json::from_str::<RGBLedColor>(r##""#ff8800""##).is_err());
+        assert!(serde_json::from_str::<Hex>(r#""nope""#).is_err());
+        assert_eq!(
+            serde_json::from_str::<crate::ParameterTypes>(r##"{"Color":"#ff8800"}"##).unwrap(),
+            crate::ParameterTypes::Color(orange)
+        );
+
+        let bytes = postcard::to_allocvec(&Hex(orange)).unwrap();
+        assert_eq!(bytes, [0xff, 0x88, 0x00]);
+        assert_eq!(postcard::from_bytes::<Hex>(&bytes).unwrap(), Hex(orange));
+        assert_eq!(postcard::from_bytes::<RGBLedColor>(&bytes).unwrap(), orange);
+    }
+
+    proptest! {
+        #[test]
+        fn text_round_trip(color in any_color()) {
+            prop_assert_eq!(color.to_string().parse(), Ok(color));
+        }
+
+        #[test]
+        fn hsv_round_trip(color in any_color()) {
+            let (h, s, v) = color.to_hsv();
+            prop_assert_eq!(RGBLedColor::from_hsv(h, s, v), color);
+        }
+
+        #[test]
+        fn hsl_round_trip(color in any_color()) {
+            let (h, s, l) = color.to_hsl();
+            prop_assert_eq!(RGBLedColor::from_hsl(h, s, l), color);
+        }
+
+        #[test]
+        fn components_in_range(color in any_color()) {
+            for (h, s, x) in [color.to_hsv(), color.to_hsl()] {
+                prop_assert!((0.0..360.0).contains(&h));
+                prop_assert!((0.0..=1.0).contains(&s));
+                prop_assert!((0.0..=1.0).contains(&x));
+            }
+        }
+
+        #[test]
+        fn hue_is_normalized(hue in any::<f32>()) {
+            prop_assert!((0.0..360.0).contains(&normalize_hue(hue)));
+        }
+
+        #[test]
+        fn saturated_colors_are_never_black(hue in -1.0e6f32..1.0e6) {
+            let color = RGBLedColor::from_hsv(hue, 1.0, 1.0);
+            prop_assert_eq!(color.red.max(color.green).max(color.blue), 255);
+        }
+
+        #[test]
+        fn hue_wraps_every_turn(hue in 0.0f32..360.0, turns in -8i32..8) {
+            let wrapped = hue + 360.0 * turns as f32;
+            let expected = RGBLedColor::from_hsv(hue, 1.0, 1.0);
+            let actual = RGBLedColor::from_hsv(wrapped, 1.0, 1.0);
+            // float error of the wrap may move a channel by one step
+            for (expected, actual) in [
+                (expected.red, actual.red),
+                (expected.green, actual.green),
+                (expected.blue, actual.blue),
+            ] {
+                prop_assert!(expected.abs_diff(actual) <= 1);
+            }
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! One type per request, tied to the type of its reply by [`Command`].
+//!
+//! Clients send them with [`Client::send`](crate::client::Client::send) and get the reply typed
+//! accordingly, controllers answer with [`Command::Response`]. Mixing up the two is then a compile
+//! error instead of a reply that fails to decode. [`Request::StreamColor`] is not answered, so
+//! it has no command. [`InZone`] sends a [`ZoneCommand`] to one zone. [`Request::Authorized`] has
+//! none either, the client adds it to every request once paired.
+
+use alloc::{boxed::Box, collections::BTreeMap, string::String, vec::Vec};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    Capability, DeviceInfo, EffectInfo, Error, Event, EventKind, ParameterDescriptor,
+    ParameterTypes, PowerState, Request, Token, VersionInfo, ZoneId, ZoneInfo,
+};
+
+pub trait Command: Into<Request> {
+    /// Payload of [`Response::result`](crate::Response::result)
+    type Response: Serialize + DeserializeOwned;
+    /// What the controller has to support to understand the request, `None` for the requests
+    /// every controller handles
+    const CAPABILITY: Option<Capability> = None;
+}
+
+macro_rules! command {
+    (@impl $name:ident => $response:ty) => {
+        impl Command for $name {
+            type Response = $response;
+        }
+    };
+    (@impl $name:ident => $response:ty, $capability:ident) => {
+        impl Command for $name {
+            type Response = $response;
+            const CAPABILITY: Option<Capability> = Some(Capability::$capability);
+        }
+    };
+    ($name:ident => $response:ty $(, $capability:ident)?) => {
+        #[doc = concat!("[`Request::", stringify!($name), "`]")]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+        pub struct $name;
+
+        impl From<$name> for Request {
+            fn from(_: $name) -> Self {
+                Request::$name
+            }
+        }
+
+        command!(@impl $name => $response $(, $capability)?);
+    };
+    ($name:ident { $($field:ident: $type:ty),+ } => $response:ty $(, $capability:ident)?) => {
+        #[doc = concat!("[`Request::", stringify!($name), "`]")]
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct $name {
+            $(pub $field: $type),+
+        }
+
+        impl From<$name> for Request {
+            fn from(command: $name) -> Self {
+                let $name { $($field),+ } = command;
+                Request::$name($($field),+)
+            }
+        }
+
+        command!(@impl $name => $response $(, $capability)?);
+    };
+}
+
+command!(Hello => VersionInfo);
+command!(GetEffects => Vec<String>);
+command!(GetEffect => String);
+command!(GetParameters => Vec<ParameterDescriptor>);
+command!(GetName => String);
+command!(SetEffect { index: usize } => ());
+command!(SetOption { key: String, value: ParameterTypes } => ParameterTypes);
+command!(
+    SetOptions { values: BTreeMap<String, ParameterTypes> }
+        => BTreeMap<String, Result<ParameterTypes, Error>>, BatchParameters
+);
+// later frames with the ID of the request are events as well
+command!(Subscribe { kinds: Vec<EventKind> } => Event, Events);
+command!(GetDeviceInfo => DeviceInfo, DeviceInfo);
+command!(SetPower { on: bool } => bool, PowerControl);
+command!(SetBrightness { brightness: f32 } => f32, PowerControl);
+command!(GetPowerState => PowerState, PowerControl);
+command!(ListEffects => Vec<EffectInfo>, EffectIds);
+command!(SetEffectById { id: String } => (), EffectIds);
+command!(GetEffectId => String, EffectIds);
+command!(GetZones => Vec<ZoneInfo>, Zones);
+command!(StartPairing => (), Pairing);
+command!(Pair { code: u32 } => Token, Pairing);
+
+/// Commands that [accept a zone](Request::accepts_zone)
+pub trait ZoneCommand: Command {}
+
+impl ZoneCommand for GetEffects {}
+impl ZoneCommand for GetEffect {}
+impl ZoneCommand for GetParameters {}
+impl ZoneCommand for SetEffect {}
+impl ZoneCommand for SetOption {}
+impl ZoneCommand for SetOptions {}
+impl ZoneCommand for ListEffects {}
+impl ZoneCommand for SetEffectById {}
+impl ZoneCommand for GetEffectId {}
+
+/// [`Request::InZone`], answered like `C`. Only [`Capability::Zones`] is checked, not the
+/// capability of `C`
+#[derive(Debug, Clone, PartialEq)]
+pub struct InZone<C> {
+    pub zone: ZoneId,
+    pub command: C,
+}
+
+impl<C: ZoneCommand> From<InZone<C>> for Request {
+    fn from(in_zone: InZone<C>) -> Self {
+        Request::InZone(in_zone.zone, Box::new(in_zone.command.into()))
+    }
+}
+
+impl<C: ZoneCommand> Command for InZone<C> {
+    type Response = C::Response;
+    const CAPABILITY: Option<Capability> = Some(Capability::Zones);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RGBLedColor;
+    use alloc::{format, vec};
+
+    #[test]
+    fn commands_build_their_request() {
+        let value = ParameterTypes::Color(RGBLedColor::new(1, 2, 3));
+        let pairs = [
+            (Request::from(Hello), Request::Hello),
+            (SetEffect { index: 3 }.into(), Request::SetEffect(3)),
+            (
+                SetOption {
+                    key: "color".into(),
+                    value: value.clone(),
+                }
+                .into(),
+                Request::SetOption("color".into(), value),
+            ),
+            (
+                Subscribe {
+                    kinds: vec![EventKind::Power],
+                }
+                .into(),
+                Request::Subscribe(vec![EventKind::Power]),
+            ),
+            (
+                SetEffectById { id: "decay".into() }.into(),
+                Request::SetEffectById("decay".into()),
+            ),
+            (
+                InZone {
+                    zone: 1,
+                    command: SetEffect { index: 2 },
+                }
+                .into(),
+                Request::InZone(1, Box::new(Request::SetEffect(2))),
+            ),
+        ];
+        for (built, expected) in pairs {
+            assert_eq!(format!("{built:?}"), format!("{expected:?}"));
+        }
+
+        assert_eq!(GetName::CAPABILITY, None);
+        assert_eq!(SetBrightness::CAPABILITY, Some(Capability::PowerControl));
+    }
+}
@@ -0,0 +1,117 @@
+//! State of the controller itself, independent of the effect: identity and health, see
+//! [`Request::GetDeviceInfo`](crate::Request::GetDeviceInfo), and the output power, see
+//! [`Request::GetPowerState`](crate::Request::GetPowerState).
+
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+/// Reply to [`Request::GetDeviceInfo`](crate::Request::GetDeviceInfo), tells boards and builds apart
+#[cfg(feature = "alloc")]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DeviceInfo {
+    pub firmware_version: String,
+    /// Commit the firmware was built from, `-dirty` when it had uncommitted changes.
+    /// `None` for builds outside of a git checkout
+    pub git_hash: Option<String>,
+    /// UTC, `YYYY-MM-DDTHH:MM:SSZ`
+    pub build_date: String,
+    /// E.g. `ESP32-C3`
+    pub chip_model: String,
+    /// `major * 100 + minor`, as ESP-IDF reports it
+    pub chip_revision: u16,
+    /// Factory programmed base MAC address, unique per board
+    pub mac_address: MacAddress,
+    pub uptime_ms: u64,
+    /// Bytes
+    pub free_heap: u32,
+    /// Lowest free heap since boot, in bytes
+    pub min_free_heap: u32,
+    /// `None` when the statistics can't be read
+    pub nvs: Option<NvsUsage>,
+    pub reset_reason: ResetReason,
+}
+
+/// Shown as `aa:bb:cc:dd:ee:ff`, serialised as six numbers
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MacAddress(pub [u8; 6]);
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, byte) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str(":")?;
+            }
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Entries of the default NVS partition, one entry is 32 bytes
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct NvsUsage {
+    pub used_entries: u32,
+    pub total_entries: u32,
+}
+
+/// Reply to [`Request::GetPowerState`](crate::Request::GetPowerState)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PowerState {
+    pub on: bool,
+    /// Master brightness, [0.0, 1.0]
+    pub brightness: f32,
+}
+
+/// Why the controller last started. New reasons are appended to the end
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum ResetReason {
+    Unknown,
+    PowerOn,
+    /// Reset pin
+    External,
+    /// `esp_restart`, e.g. after a firmware update
+    Software,
+    Panic,
+    InterruptWatchdog,
+    TaskWatchdog,
+    /// Other watchdogs
+    Watchdog,
+    DeepSleep,
+    Brownout,
+    Sdio,
+    Usb,
+    Jtag,
+    EfuseError,
+    PowerGlitch,
+    CpuLockup,
+}
+
+impl fmt::Display for ResetReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ResetReason::Unknown => "unknown",
+            ResetReason::PowerOn => "power on",
+            ResetReason::External => "reset pin",
+            ResetReason::Software => "software restart",
+            ResetReason::Panic => "panic",
+            ResetReason::InterruptWatchdog => "interrupt watchdog",
+            ResetReason::TaskWatchdog => "task watchdog",
+            ResetReason::Watchdog => "watchdog",
+            ResetReason::DeepSleep => "wake from deep sleep",
+            ResetReason::Brownout => "brownout",
+            ResetReason::Sdio => "SDIO",
+            ResetReason::Usb => "USB",
+            ResetReason::Jtag => "JTAG",
+            ResetReason::EfuseError => "eFuse error",
+            ResetReason::PowerGlitch => "power glitch",
+            ResetReason::CpuLockup => "CPU lockup",
+        })
+    }
+}
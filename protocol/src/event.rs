@@ -0,0 +1,72 @@
+//! Messages the controller pushes on its own.
+//!
+//! A client asks for them with [`Request::Subscribe`](crate::Request::Subscribe). Events are sent as
+//! [`Response`](crate::Response)s carrying the ID of that request, so clients that never subscribe
+//! don't see them. The reply to the request itself is the first of them, [`Event::Subscribed`].
+//! Changes made by the subscriber itself are reported as well.
+
+#[cfg(feature = "alloc")]
+use alloc::{string::String, vec::Vec};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "alloc")]
+use crate::{Error, ParameterDescriptor, ParameterTypes};
+
+/// Groups of events a client can subscribe to. New kinds are appended to the end
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum EventKind {
+    Effect,
+    Parameter,
+    /// Power and brightness
+    Power,
+    Error,
+}
+
+impl EventKind {
+    pub const ALL: &'static [EventKind] = &[
+        EventKind::Effect,
+        EventKind::Parameter,
+        EventKind::Power,
+        EventKind::Error,
+    ];
+}
+
+/// New events are appended to the end, so the binary encoding of the existing ones stays the same
+#[cfg(feature = "alloc")]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Event {
+    /// Confirms a subscription, lists the kinds that will be reported
+    Subscribed(Vec<EventKind>),
+    /// Another effect was selected, carries its parameters
+    EffectChanged {
+        /// [`EffectInfo::id`](crate::EffectInfo::id)
+        id: String,
+        name: String,
+        parameters: Vec<ParameterDescriptor>,
+    },
+    /// A parameter of the current effect got a new value
+    ParameterChanged {
+        key: String,
+        value: ParameterTypes,
+    },
+    PowerChanged(bool),
+    BrightnessChanged(f32),
+    /// Something failed on the controller outside of a request, e.g. a PWM or storage error
+    Error(Error),
+}
+
+#[cfg(feature = "alloc")]
+impl Event {
+    /// Kind a client has to subscribe to for this event, `None` for events every subscriber gets
+    pub fn kind(&self) -> Option<EventKind> {
+        match self {
+            Event::Subscribed(_) => None,
+            Event::EffectChanged { .. } => Some(EventKind::Effect),
+            Event::ParameterChanged { .. } => Some(EventKind::Parameter),
+            Event::PowerChanged(_) | Event::BrightnessChanged(_) => Some(EventKind::Power),
+            Event::Error(_) => Some(EventKind::Error),
+        }
+    }
+}
@@ -0,0 +1,629 @@
+//! Heap-free counterparts of the messages a controller receives, for firmware without `alloc`.
+//!
+//! They have the same wire format as the types in the crate root and have to be kept in sync
+//! with them. Strings and collections are `heapless` with the capacities below, a request that
+//! doesn't fit fails to decode like any other malformed one. Replies borrow their data instead,
+//! see [`VersionInfo`], [`DeviceInfo`], [`EffectInfo`] and [`ParameterDescriptor`].
+//!
+//! Colours are only read in their struct form, the text forms behind
+//! [`Capability::ColorStrings`] need `alloc`. Decode with [`framing::decode_in_place`] and
+//! encode with [`framing::encode_into`].
+//!
+//! [`framing::decode_in_place`]: crate::framing::decode_in_place
+//! [`framing::encode_into`]: crate::framing::encode_into
+
+use core::fmt::{self, Write};
+use heapless::{LinearMap, String, Vec};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{
+    palette, Capability, ChannelLayout, ColorStop, ErrorCode, EventKind, Interpolation, MacAddress,
+    NvsUsage, ProtocolVersion, RGBLedColor, ResetReason, Token, ZoneId,
+};
+
+/// Longest parameter key and [`ParameterTypes::Choice`] option
+pub const MAX_KEY_LENGTH: usize = 32;
+/// Longest [`ParameterTypes::Text`]
+pub const MAX_TEXT_LENGTH: usize = 64;
+pub const MAX_CHOICE_OPTIONS: usize = 8;
+/// Most keys in one [`Request::SetOptions`]
+pub const MAX_BATCH_PARAMETERS: usize = 8;
+/// Most kinds in one [`Request::Subscribe`]
+pub const MAX_EVENT_KINDS: usize = 8;
+/// Longest [`Error`] message, longer ones are cut
+pub const MAX_MESSAGE_LENGTH: usize = 64;
+
+pub type Key = String<MAX_KEY_LENGTH>;
+pub type Text = String<MAX_TEXT_LENGTH>;
+
+/// [`crate::ParameterTypes`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ParameterTypes {
+    Color(RGBLedColor),
+    Float(f32),
+    Int(i32),
+    Bool(bool),
+    Choice {
+        selected: usize,
+        options: Vec<Key, MAX_CHOICE_OPTIONS>,
+    },
+    Text(Text),
+    Palette(Palette),
+    Kelvin(u16),
+}
+
+/// [`crate::Palette`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Palette {
+    #[serde(deserialize_with = "deserialize_stops")]
+    pub stops: Vec<ColorStop, { palette::MAX_STOPS }>,
+    pub interpolation: Interpolation,
+}
+
+impl Palette {
+    /// Creates a palette, stops are sorted by position
+    pub fn new(
+        mut stops: Vec<ColorStop, { palette::MAX_STOPS }>,
+        interpolation: Interpolation,
+    ) -> Self {
+        palette::sort_stops(&mut stops);
+        Self {
+            stops,
+            interpolation,
+        }
+    }
+
+    /// Colour at `t`, see [`crate::Palette::sample`]
+    pub fn sample(&self, t: f32) -> RGBLedColor {
+        palette::sample(&self.stops, self.interpolation, t)
+    }
+}
+
+fn deserialize_stops<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<ColorStop, { palette::MAX_STOPS }>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(rename = "ColorStop")]
+    struct Stop {
+        position: f32,
+        color: RGBLedColor,
+    }
+
+    let stops = Vec::<Stop, { palette::MAX_STOPS }>::deserialize(deserializer)?;
+    Ok(stops
+        .into_iter()
+        .map(|stop| ColorStop::new(stop.position, stop.color))
+        .collect())
+}
+
+/// [`crate::Request`], about 3 KB with the default capacities. `Z` is what
+/// [`Request::InZone`] carries and `A` what [`Request::Authorized`] does. At the top level
+/// those are a [`ZonedRequest`] and an [`AuthorizedRequest`], below them [`Unnested`]: neither
+/// nests and a zone can't wrap an authorization
+// boxing the batch would need a heap
+#[allow(clippy::large_enum_variant)]
+#[derive(Deserialize, Debug)]
+pub enum Request<Z = ZonedRequest, A = AuthorizedRequest> {
+    Hello,
+    GetEffects,
+    GetEffect,
+    GetParameters,
+    GetName,
+    SetEffect(usize),
+    SetOption(Key, ParameterTypes),
+    SetOptions(LinearMap<Key, ParameterTypes, MAX_BATCH_PARAMETERS>),
+    Subscribe(Vec<EventKind, MAX_EVENT_KINDS>),
+    StreamColor { color: RGBLedColor, timeout_ms: u32 },
+    GetDeviceInfo,
+    SetPower(bool),
+    SetBrightness(f32),
+    GetPowerState,
+    ListEffects,
+    SetEffectById(Key),
+    GetEffectId,
+    GetZones,
+    InZone(ZoneId, Z),
+    StartPairing,
+    Pair(u32),
+    Authorized(Token, A),
+}
+
+/// Request wrapped in [`Request::InZone`]
+pub type ZonedRequest = Request<Unnested, Unnested>;
+
+/// Request wrapped in [`Request::Authorized`], it may address a zone
+pub type AuthorizedRequest = Request<ZonedRequest, Unnested>;
+
+/// Nothing, a wrapper where it can't be fails to decode
+#[derive(Deserialize, Debug)]
+pub enum Unnested {}
+
+impl<Z, A> Request<Z, A> {
+    /// [`crate::Request::accepts_zone`]
+    pub fn accepts_zone(&self) -> bool {
+        matches!(
+            self,
+            Request::GetEffects
+                | Request::GetEffect
+                | Request::GetParameters
+                | Request::SetEffect(_)
+                | Request::SetOption(..)
+                | Request::SetOptions(_)
+                | Request::StreamColor { .. }
+                | Request::ListEffects
+                | Request::SetEffectById(_)
+                | Request::GetEffectId
+        )
+    }
+
+    /// [`crate::Request::needs_token`]
+    pub fn needs_token(&self) -> bool {
+        !matches!(
+            self,
+            Request::Hello | Request::StartPairing | Request::Pair(_) | Request::Authorized(..)
+        )
+    }
+}
+
+/// [`crate::RequestFrame`]
+#[derive(Deserialize, Debug)]
+pub struct RequestFrame {
+    pub id: u32,
+    pub request: Request,
+}
+
+/// [`crate::Error`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub code: ErrorCode,
+    pub message: String<MAX_MESSAGE_LENGTH>,
+}
+
+impl Error {
+    /// `message` is cut to [`MAX_MESSAGE_LENGTH`] bytes
+    pub fn new(code: ErrorCode, message: impl fmt::Display) -> Self {
+        let mut writer = Truncating(String::new());
+        let _ = write!(writer, "{message}");
+        Self {
+            code,
+            message: writer.0,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl core::error::Error for Error {}
+
+/// Writes as much as fits, stops at the first character that doesn't
+struct Truncating(String<MAX_MESSAGE_LENGTH>);
+
+impl Write for Truncating {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for character in s.chars() {
+            self.0.push(character).map_err(|_| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
+
+/// [`crate::Response`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Response<T> {
+    pub id: Option<u32>,
+    pub result: Result<T, Error>,
+}
+
+impl<T> Response<T> {
+    pub fn new(id: Option<u32>, result: Result<T, Error>) -> Self {
+        Self { id, result }
+    }
+}
+
+/// [`crate::VersionInfo`], the reply to [`Request::Hello`]
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct VersionInfo<'a> {
+    pub protocol_version: ProtocolVersion,
+    pub firmware_version: &'a str,
+    pub capabilities: &'a [Capability],
+}
+
+/// [`crate::DeviceInfo`], the reply to [`Request::GetDeviceInfo`]
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct DeviceInfo<'a> {
+    pub firmware_version: &'a str,
+    pub git_hash: Option<&'a str>,
+    pub build_date: &'a str,
+    pub chip_model: &'a str,
+    pub chip_revision: u16,
+    pub mac_address: MacAddress,
+    pub uptime_ms: u64,
+    pub free_heap: u32,
+    pub min_free_heap: u32,
+    pub nvs: Option<NvsUsage>,
+    pub reset_reason: ResetReason,
+}
+
+/// [`crate::EffectInfo`], a slice of them is the reply to [`Request::ListEffects`]
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct EffectInfo<'a> {
+    pub id: &'a str,
+    pub name: &'a str,
+}
+
+/// [`crate::ZoneInfo`], a slice of them is the reply to [`Request::GetZones`]
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct ZoneInfo<'a> {
+    pub id: ZoneId,
+    pub name: &'a str,
+    pub channels: ChannelLayout,
+}
+
+/// [`crate::ParameterDescriptor`], a slice of them is the reply to [`Request::GetParameters`]
+#[derive(Serialize, Debug, Clone)]
+pub struct ParameterDescriptor<'a> {
+    pub key: &'a str,
+    pub label: &'a str,
+    pub value: ParameterTypes,
+    pub default: ParameterTypes,
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+    pub step: Option<f32>,
+    pub unit: Option<&'a str>,
+    pub group: Option<&'a str>,
+}
+
+#[cfg(feature = "alloc")]
+impl From<Error> for crate::Error {
+    fn from(err: Error) -> Self {
+        crate::Error::new(err.code, err.message.as_str())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl From<ParameterTypes> for crate::ParameterTypes {
+    fn from(value: ParameterTypes) -> Self {
+        match value {
+            ParameterTypes::Color(color) => Self::Color(color),
+            ParameterTypes::Float(value) => Self::Float(value),
+            ParameterTypes::Int(value) => Self::Int(value),
+            ParameterTypes::Bool(value) => Self::Bool(value),
+            ParameterTypes::Choice { selected, options } => Self::Choice {
+                selected,
+                options: options
+                    .iter()
+                    .map(|option| option.as_str().into())
+                    .collect(),
+            },
+            ParameterTypes::Text(text) => Self::Text(text.as_str().into()),
+            ParameterTypes::Palette(palette) => Self::Palette(crate::Palette {
+                stops: palette.stops.to_vec(),
+                interpolation: palette.interpolation,
+            }),
+            ParameterTypes::Kelvin(value) => Self::Kelvin(value),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl From<Unnested> for crate::Request {
+    fn from(unnested: Unnested) -> Self {
+        match unnested {}
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<Z: Into<crate::Request>, A: Into<crate::Request>> From<Request<Z, A>> for crate::Request {
+    fn from(request: Request<Z, A>) -> Self {
+        match request {
+            Request::Hello => Self::Hello,
+            Request::GetEffects => Self::GetEffects,
+            Request::GetEffect => Self::GetEffect,
+            Request::GetParameters => Self::GetParameters,
+            Request::GetName => Self::GetName,
+            Request::SetEffect(index) => Self::SetEffect(index),
+            Request::SetOption(key, value) => Self::SetOption(key.as_str().into(), value.into()),
+            Request::SetOptions(values) => Self::SetOptions(
+                values
+                    .into_iter()
+                    .map(|(key, value)| (key.as_str().into(), value.clone().into()))
+                    .collect(),
+            ),
+            Request::Subscribe(kinds) => Self::Subscribe(kinds.to_vec()),
+            Request::StreamColor { color, timeout_ms } => Self::StreamColor { color, timeout_ms },
+            Request::GetDeviceInfo => Self::GetDeviceInfo,
+            Request::SetPower(on) => Self::SetPower(on),
+            Request::SetBrightness(brightness) => Self::SetBrightness(brightness),
+            Request::GetPowerState => Self::GetPowerState,
+            Request::ListEffects => Self::ListEffects,
+            Request::SetEffectById(id) => Self::SetEffectById(id.as_str().into()),
+            Request::GetEffectId => Self::GetEffectId,
+            Request::GetZones => Self::GetZones,
+            Request::InZone(zone, request) => {
+                Self::InZone(zone, alloc::boxed::Box::new(request.into()))
+            }
+            Request::StartPairing => Self::StartPairing,
+            Request::Pair(code) => Self::Pair(code),
+            Request::Authorized(token, request) => {
+                Self::Authorized(token, alloc::boxed::Box::new(request.into()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framing::{self, Encoding, FixedFrameDecoder};
+    use alloc::{boxed::Box, format, vec};
+
+    const ENCODINGS: [Encoding; 3] = [Encoding::Json, Encoding::JsonChecksum, Encoding::Binary];
+
+    /// Runs `frame` through a [`FixedFrameDecoder`] and decodes it in place
+    fn decode_frame<'a, T: Deserialize<'a>>(
+        decoder: &'a mut FixedFrameDecoder<512>,
+        frame: &[u8],
+    ) -> Result<T, Error> {
+        let (last, bytes) = frame.split_last().unwrap();
+        for byte in bytes {
+            assert!(decoder.push(*byte).is_none());
+        }
+        let (encoding, frame) = decoder.push(*last).expect("frame is complete");
+        framing::decode_in_place(encoding, frame)
+    }
+
+    #[test]
+    fn requests_decode_without_heap() {
+        let palette = crate::Palette::new(
+            vec![
+                ColorStop::new(0.0, RGBLedColor::new(255, 0, 0)),
+                ColorStop::new(1.0, RGBLedColor::new(0, 0, 255)),
+            ],
+            Interpolation::Stepped,
+        );
+        let requests = [
+            crate::Request::Hello,
+            crate::Request::SetEffect(3),
+            crate::Request::SetOption(
+                "color".into(),
+                crate::ParameterTypes::Color(RGBLedColor::new(1, 2, 3)),
+            ),
+            crate::Request::SetOption(
+                "mode".into(),
+                crate::ParameterTypes::Choice {
+                    selected: 1,
+                    options: vec!["fade".into(), "blink".into()],
+                },
+            ),
+            crate::Request::SetOptions(
+                [
+                    ("palette".into(), crate::ParameterTypes::Palette(palette)),
+                    ("speed".into(), crate::ParameterTypes::Float(0.5)),
+                    ("name".into(), crate::ParameterTypes::Text("desk".into())),
+                    ("temperature".into(), crate::ParameterTypes::Kelvin(2700)),
+                ]
+                .into_iter()
+                .collect(),
+            ),
+            crate::Request::Subscribe(EventKind::ALL.to_vec()),
+            crate::Request::StreamColor {
+                color: RGBLedColor::new(10, 20, 30),
+                timeout_ms: 500,
+            },
+            crate::Request::GetDeviceInfo,
+            crate::Request::SetPower(false),
+            crate::Request::SetBrightness(0.25),
+            crate::Request::SetEffectById("hue_rotate".into()),
+            crate::Request::GetZones,
+            crate::Request::InZone(1, Box::new(crate::Request::SetEffect(2))),
+            crate::Request::InZone(
+                2,
+                Box::new(crate::Request::SetOption(
+                    "speed".into(),
+                    crate::ParameterTypes::Float(0.5),
+                )),
+            ),
+            crate::Request::StartPairing,
+            crate::Request::Pair(123456),
+            crate::Request::Authorized(Token([9; 16]), Box::new(crate::Request::GetName)),
+            crate::Request::Authorized(
+                Token([9; 16]),
+                Box::new(crate::Request::InZone(
+                    1,
+                    Box::new(crate::Request::GetEffectId),
+                )),
+            ),
+        ];
+
+        // same fields as `RequestFrame`, which would need an owned request
+        #[derive(Serialize)]
+        struct Frame<'a> {
+            id: u32,
+            request: &'a crate::Request,
+        }
+
+        for encoding in ENCODINGS {
+            for (id, request) in requests.iter().enumerate() {
+                let id = id as u32;
+                let frame = framing::encode(encoding, &Frame { id, request });
+                let mut decoder = FixedFrameDecoder::new();
+                let decoded: RequestFrame = decode_frame(&mut decoder, &frame)
+                    .unwrap_or_else(|err| panic!("{request:?} as {encoding:?}: {err}"));
+                assert_eq!(decoded.id, id);
+                assert_eq!(
+                    format!("{:?}", crate::Request::from(decoded.request)),
+                    format!("{request:?}"),
+                    "{encoding:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_requests_are_rejected() {
+        let request = crate::RequestFrame {
+            id: 1,
+            request: crate::Request::SetOption(
+                "k".repeat(MAX_KEY_LENGTH + 1),
+                crate::ParameterTypes::Int(1),
+            ),
+        };
+        for encoding in ENCODINGS {
+            let mut decoder = FixedFrameDecoder::new();
+            let frame = framing::encode(encoding, &request);
+            let err = decode_frame::<RequestFrame>(&mut decoder, &frame).unwrap_err();
+            assert_eq!(err.code, ErrorCode::MalformedRequest);
+        }
+
+        // zones don't nest
+        let nested = crate::Request::InZone(1, Box::new(crate::Request::GetZones));
+        let nested = crate::RequestFrame {
+            id: 1,
+            request: crate::Request::InZone(1, Box::new(nested)),
+        };
+        for encoding in ENCODINGS {
+            let mut decoder = FixedFrameDecoder::new();
+            let frame = framing::encode(encoding, &nested);
+            let err = decode_frame::<RequestFrame>(&mut decoder, &frame).unwrap_err();
+            assert_eq!(err.code, ErrorCode::MalformedRequest);
+        }
+
+        // neither do authorizations, and a zone doesn't wrap one
+        let authorized = |request| crate::Request::Authorized(Token([1; 16]), Box::new(request));
+        for request in [
+            authorized(authorized(crate::Request::GetName)),
+            crate::Request::InZone(1, Box::new(authorized(crate::Request::GetEffect))),
+        ] {
+            let frame = crate::RequestFrame { id: 1, request };
+            for encoding in ENCODINGS {
+                let mut decoder = FixedFrameDecoder::new();
+                let frame = framing::encode(encoding, &frame);
+                let err = decode_frame::<RequestFrame>(&mut decoder, &frame).unwrap_err();
+                assert_eq!(err.code, ErrorCode::MalformedRequest);
+            }
+        }
+
+        // longer than the decoder buffer, dropped up to the next delimiter
+        let mut decoder = FixedFrameDecoder::<16>::new();
+        let frame = framing::encode(Encoding::Json, &request);
+        assert!(frame.iter().all(|byte| decoder.push(*byte).is_none()));
+        let frame = framing::encode(Encoding::Json, &crate::RequestHeader { id: 2 });
+        assert!(frame[..frame.len() - 1]
+            .iter()
+            .all(|byte| decoder.push(*byte).is_none()));
+        assert!(decoder.push(b'\n').is_some());
+    }
+
+    #[test]
+    fn replies_match_alloc_encoding() {
+        let capabilities = [Capability::ColorParameter, Capability::BinaryFraming];
+        let version = Response::new(
+            Some(7),
+            Ok(VersionInfo {
+                protocol_version: crate::PROTOCOL_VERSION,
+                firmware_version: "0.1.0",
+                capabilities: &capabilities,
+            }),
+        );
+        let expected_version = crate::Response::new(
+            Some(7),
+            Ok(crate::VersionInfo {
+                protocol_version: crate::PROTOCOL_VERSION,
+                firmware_version: "0.1.0".into(),
+                capabilities: capabilities.to_vec(),
+            }),
+        );
+        let device = Response::new(
+            Some(9),
+            Ok(DeviceInfo {
+                firmware_version: "0.1.0",
+                git_hash: Some("4a1b2c3d5e"),
+                build_date: "2026-10-18T09:30:00Z",
+                chip_model: "ESP32-C3",
+                chip_revision: 4,
+                mac_address: MacAddress([0x34, 0x85, 0x18, 0x01, 0x02, 0x03]),
+                uptime_ms: 86_400_000,
+                free_heap: 210_000,
+                min_free_heap: 180_000,
+                nvs: Some(NvsUsage {
+                    used_entries: 40,
+                    total_entries: 756,
+                }),
+                reset_reason: ResetReason::PowerOn,
+            }),
+        );
+        let expected_device = crate::Response::new(
+            Some(9),
+            Ok(crate::DeviceInfo {
+                firmware_version: "0.1.0".into(),
+                git_hash: Some("4a1b2c3d5e".into()),
+                build_date: "2026-10-18T09:30:00Z".into(),
+                chip_model: "ESP32-C3".into(),
+                chip_revision: 4,
+                mac_address: MacAddress([0x34, 0x85, 0x18, 0x01, 0x02, 0x03]),
+                uptime_ms: 86_400_000,
+                free_heap: 210_000,
+                min_free_heap: 180_000,
+                nvs: Some(NvsUsage {
+                    used_entries: 40,
+                    total_entries: 756,
+                }),
+                reset_reason: ResetReason::PowerOn,
+            }),
+        );
+        // long enough for several COBS blocks
+        let name = "n".repeat(600);
+        let error = Response::<()>::new(
+            None,
+            Err(Error::new(ErrorCode::CorruptFrame, "missing checksum")),
+        );
+        let expected_error = crate::Response::<()>::new(
+            None,
+            Err(crate::Error::new(
+                ErrorCode::CorruptFrame,
+                "missing checksum",
+            )),
+        );
+
+        let mut buffer = [0u8; 1024];
+        for encoding in ENCODINGS {
+            let frame = framing::encode_into(encoding, &version, &mut buffer).unwrap();
+            assert_eq!(
+                frame,
+                framing::encode(encoding, &expected_version),
+                "{encoding:?}"
+            );
+
+            let frame = framing::encode_into(encoding, &device, &mut buffer).unwrap();
+            assert_eq!(
+                frame,
+                framing::encode(encoding, &expected_device),
+                "{encoding:?}"
+            );
+
+            let reply = Response::new(Some(8), Ok(name.as_str()));
+            let frame = framing::encode_into(encoding, &reply, &mut buffer).unwrap();
+            assert_eq!(frame, framing::encode(encoding, &reply), "{encoding:?}");
+
+            let frame = framing::encode_into(encoding, &error, &mut buffer).unwrap();
+            assert_eq!(
+                frame,
+                framing::encode(encoding, &expected_error),
+                "{encoding:?}"
+            );
+
+            assert!(framing::encode_into(encoding, &version, &mut buffer[..16]).is_none());
+        }
+    }
+
+    #[test]
+    fn error_messages_are_cut() {
+        let err = Error::new(ErrorCode::StorageFailure, "é".repeat(MAX_MESSAGE_LENGTH));
+        assert_eq!(err.message.len(), MAX_MESSAGE_LENGTH);
+        assert!(err.message.chars().all(|character| character == 'é'));
+    }
+}
@@ -0,0 +1,790 @@
+//! Wire encodings of the protocol messages.
+//!
+//! JSON frames are newline terminated objects, so the link stays readable in a terminal.
+//! They may end with an optional `*XXXX` suffix: the CRC-16 of the JSON text in hex.
+//! Binary frames are postcard encoded, followed by a big endian CRC-16 of the payload,
+//! COBS stuffed and wrapped in `0x00` on both sides: `0x00 <cobs data> 0x00`.
+//! The leading zero tells the receiver a binary frame follows, so both encodings can share
+//! one link and every reply uses the encoding of its request.
+//!
+//! Lines that don't start with `{` (boot banners, logs, line noise) are skipped up to the
+//! next delimiter. A stray zero in front of a text line doesn't hide it, the line ends binary
+//! mode again. The CRC is CRC-16/IBM-3740 (also known as CCITT-FALSE).
+//!
+//! [`encode`], [`decode`] and [`FrameDecoder`] need `alloc`. [`encode_into`], [`decode_in_place`]
+//! and [`FixedFrameDecoder`] work on caller buffers, JSON goes through serde-json-core there,
+//! which rejects strings with escapes.
+
+#[cfg(feature = "alloc")]
+use alloc::{format, string::ToString, vec, vec::Vec};
+use core::mem;
+use crc::{Crc, Digest, CRC_16_IBM_3740};
+use postcard::ser_flavors::{Cobs, Flavor, Slice};
+#[cfg(feature = "alloc")]
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "alloc")]
+use crate::Error;
+use crate::{fixed, ErrorCode};
+
+pub const BINARY_DELIMITER: u8 = 0x00;
+pub const JSON_DELIMITER: u8 = b'\n';
+pub const JSON_CHECKSUM_MARKER: u8 = b'*';
+/// Frames longer than this are dropped, the decoder then waits for the next delimiter
+pub const MAX_FRAME_LENGTH: usize = 4096;
+
+const CRC16: Crc<u16> = Crc::<u16>::new(&CRC_16_IBM_3740);
+/// `*` followed by four hex digits
+const JSON_CHECKSUM_LENGTH: usize = 5;
+const HEX_DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Json,
+    /// JSON with the `*XXXX` CRC suffix
+    JsonChecksum,
+    /// Postcard + CRC-16 + COBS
+    Binary,
+}
+
+/// Encodes `message` into a complete frame, including delimiters
+#[cfg(feature = "alloc")]
+pub fn encode<T: Serialize>(encoding: Encoding, message: &T) -> Vec<u8> {
+    match encoding {
+        Encoding::Json => {
+            let mut frame = serde_json::to_vec(message).expect("protocol types serialize to JSON");
+            frame.push(JSON_DELIMITER);
+            frame
+        }
+        Encoding::JsonChecksum => {
+            let mut frame = serde_json::to_vec(message).expect("protocol types serialize to JSON");
+            let checksum = CRC16.checksum(&frame);
+            frame.extend(format!("*{checksum:04X}").as_bytes());
+            frame.push(JSON_DELIMITER);
+            frame
+        }
+        Encoding::Binary => {
+            let mut payload =
+                postcard::to_allocvec(message).expect("protocol types serialize to postcard");
+            payload.extend(CRC16.checksum(&payload).to_be_bytes());
+
+            let mut frame = vec![BINARY_DELIMITER];
+            frame.extend(cobs::encode_vec(&payload));
+            frame.push(BINARY_DELIMITER);
+            frame
+        }
+    }
+}
+
+/// Decodes frame contents as returned by [`FrameDecoder::push`], without delimiters.
+/// A frame that fails its checksum is reported as [`ErrorCode::CorruptFrame`]
+#[cfg(feature = "alloc")]
+pub fn decode<T: DeserializeOwned>(encoding: Encoding, frame: &[u8]) -> Result<T, Error> {
+    match encoding {
+        Encoding::Json => serde_json::from_slice(frame)
+            .map_err(|err| Error::new(ErrorCode::MalformedRequest, err.to_string())),
+        Encoding::JsonChecksum => decode(Encoding::Json, strip_json_checksum(frame)?),
+        Encoding::Binary => {
+            let payload = cobs::decode_vec(frame)
+                .map_err(|err| Error::new(ErrorCode::CorruptFrame, err.to_string()))?;
+            postcard::from_bytes(strip_binary_checksum(&payload)?)
+                .map_err(|err| Error::new(ErrorCode::MalformedRequest, err.to_string()))
+        }
+    }
+}
+
+/// Like [`encode`], into `buffer`. Returns the frame, `None` if it doesn't fit
+pub fn encode_into<'a, T: Serialize>(
+    encoding: Encoding,
+    message: &T,
+    buffer: &'a mut [u8],
+) -> Option<&'a mut [u8]> {
+    match encoding {
+        Encoding::Json | Encoding::JsonChecksum => {
+            let mut length = serde_json_core::to_slice(message, buffer).ok()?;
+            if encoding == Encoding::JsonChecksum {
+                let checksum = CRC16.checksum(&buffer[..length]);
+                let suffix = buffer.get_mut(length..length + JSON_CHECKSUM_LENGTH)?;
+                suffix[0] = JSON_CHECKSUM_MARKER;
+                for (digit, shift) in suffix[1..].iter_mut().zip([12, 8, 4, 0]) {
+                    *digit = HEX_DIGITS[(checksum >> shift) as usize & 0xF];
+                }
+                length += JSON_CHECKSUM_LENGTH;
+            }
+            *buffer.get_mut(length)? = JSON_DELIMITER;
+            Some(&mut buffer[..=length])
+        }
+        Encoding::Binary => {
+            let (delimiter, rest) = buffer.split_first_mut()?;
+            *delimiter = BINARY_DELIMITER;
+            let flavor = Checksummed {
+                inner: Cobs::try_new(Slice::new(rest)).ok()?,
+                digest: CRC16.digest(),
+            };
+            // the COBS flavor ends with the closing delimiter
+            let length = postcard::serialize_with_flavor(message, flavor).ok()?.len();
+            Some(&mut buffer[..1 + length])
+        }
+    }
+}
+
+/// Like [`decode`], without a heap. Binary frames are COBS decoded in place, so `T` may borrow
+/// from `frame`
+pub fn decode_in_place<'a, T: Deserialize<'a>>(
+    encoding: Encoding,
+    frame: &'a mut [u8],
+) -> Result<T, fixed::Error> {
+    match encoding {
+        Encoding::Json => serde_json_core::from_slice(frame)
+            .map(|(value, _)| value)
+            .map_err(|err| fixed::Error::new(ErrorCode::MalformedRequest, err)),
+        Encoding::JsonChecksum => {
+            let length = strip_json_checksum(frame)?.len();
+            decode_in_place(Encoding::Json, &mut frame[..length])
+        }
+        Encoding::Binary => {
+            let length = cobs::decode_in_place(frame)
+                .map_err(|err| fixed::Error::new(ErrorCode::CorruptFrame, err))?;
+            let payload: &'a [u8] = &frame[..length];
+            postcard::from_bytes(strip_binary_checksum(payload)?)
+                .map_err(|err| fixed::Error::new(ErrorCode::MalformedRequest, err))
+        }
+    }
+}
+
+/// JSON text of a frame with the `*XXXX` suffix, once the checksum matched
+fn strip_json_checksum(frame: &[u8]) -> Result<&[u8], fixed::Error> {
+    let (json, checksum) = frame
+        .split_at_checked(frame.len().saturating_sub(JSON_CHECKSUM_LENGTH))
+        .filter(|(_, checksum)| checksum.first() == Some(&JSON_CHECKSUM_MARKER))
+        .ok_or_else(|| fixed::Error::new(ErrorCode::CorruptFrame, "missing checksum"))?;
+    let expected = core::str::from_utf8(&checksum[1..])
+        .ok()
+        .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+        .ok_or_else(|| fixed::Error::new(ErrorCode::CorruptFrame, "invalid checksum"))?;
+    verify_checksum(json, expected)?;
+    Ok(json)
+}
+
+/// Postcard payload of a COBS decoded frame, once the checksum matched
+fn strip_binary_checksum(payload: &[u8]) -> Result<&[u8], fixed::Error> {
+    let (payload, checksum) = payload
+        .split_last_chunk::<2>()
+        .ok_or_else(|| fixed::Error::new(ErrorCode::CorruptFrame, "frame is too short"))?;
+    verify_checksum(payload, u16::from_be_bytes(*checksum))?;
+    Ok(payload)
+}
+
+fn verify_checksum(data: &[u8], expected: u16) -> Result<(), fixed::Error> {
+    let actual = CRC16.checksum(data);
+    if actual != expected {
+        return Err(fixed::Error::new(
+            ErrorCode::CorruptFrame,
+            format_args!("checksum mismatch, expected {expected:04X}, got {actual:04X}"),
+        ));
+    }
+    Ok(())
+}
+
+/// Appends the big endian CRC-16 of everything serialised before it, for [`encode_into`]
+struct Checksummed<'a, F> {
+    inner: F,
+    digest: Digest<'a, u16>,
+}
+
+impl<F: Flavor> Flavor for Checksummed<'_, F> {
+    type Output = F::Output;
+
+    fn try_push(&mut self, data: u8) -> postcard::Result<()> {
+        self.digest.update(&[data]);
+        self.inner.try_push(data)
+    }
+
+    fn try_extend(&mut self, data: &[u8]) -> postcard::Result<()> {
+        self.digest.update(data);
+        self.inner.try_extend(data)
+    }
+
+    fn finalize(self) -> postcard::Result<Self::Output> {
+        let Self { mut inner, digest } = self;
+        inner.try_extend(&digest.finalize().to_be_bytes())?;
+        inner.finalize()
+    }
+}
+
+/// Storage of the frame being read
+trait FrameBuffer: Default {
+    fn as_slice(&self) -> &[u8];
+    /// Returns `false` when the buffer is full
+    fn push(&mut self, byte: u8) -> bool;
+    fn clear(&mut self);
+    fn truncate(&mut self, length: usize);
+    /// Drops the first `length` bytes
+    fn remove_front(&mut self, length: usize);
+}
+
+#[cfg(feature = "alloc")]
+impl FrameBuffer for Vec<u8> {
+    fn as_slice(&self) -> &[u8] {
+        self
+    }
+
+    fn push(&mut self, byte: u8) -> bool {
+        Vec::push(self, byte);
+        true
+    }
+
+    fn clear(&mut self) {
+        Vec::clear(self)
+    }
+
+    fn truncate(&mut self, length: usize) {
+        Vec::truncate(self, length)
+    }
+
+    fn remove_front(&mut self, length: usize) {
+        self.drain(..length);
+    }
+}
+
+impl<const N: usize> FrameBuffer for heapless::Vec<u8, N> {
+    fn as_slice(&self) -> &[u8] {
+        self
+    }
+
+    fn push(&mut self, byte: u8) -> bool {
+        heapless::Vec::push(self, byte).is_ok()
+    }
+
+    fn clear(&mut self) {
+        heapless::Vec::clear(self)
+    }
+
+    fn truncate(&mut self, length: usize) {
+        heapless::Vec::truncate(self, length)
+    }
+
+    fn remove_front(&mut self, length: usize) {
+        self.copy_within(length.., 0);
+        heapless::Vec::truncate(self, self.len() - length)
+    }
+}
+
+/// Splits a byte stream into frames, shared by [`FrameDecoder`] and [`FixedFrameDecoder`]
+#[derive(Debug, Default)]
+struct Splitter<B> {
+    buffer: B,
+    encoding: Option<Encoding>,
+    /// Skipping noise or an oversized frame until the next delimiter
+    discarding: bool,
+    /// `buffer` holds the last frame, it is cleared on the next byte
+    complete: bool,
+}
+
+impl<B: FrameBuffer> Splitter<B> {
+    /// Feeds one byte, returns the encoding of the frame it completes. The frame is left in `buffer`
+    fn push(&mut self, byte: u8) -> Option<Encoding> {
+        if mem::take(&mut self.complete) {
+            self.buffer.clear();
+        }
+
+        match (self.encoding, byte) {
+            (Some(Encoding::Binary), BINARY_DELIMITER) => {
+                // consecutive zeros are frame separators, not empty frames
+                if self.buffer.as_slice().is_empty() && !self.discarding {
+                    return None;
+                }
+                return self.take(Encoding::Binary);
+            }
+            // start of a binary frame, interrupts whatever text came before it
+            (_, BINARY_DELIMITER) => {
+                self.buffer.clear();
+                self.discarding = false;
+                self.encoding = Some(Encoding::Binary);
+                return None;
+            }
+            // a stray zero (a reset, line noise) put a text line into binary mode, leave it again.
+            // The JSON frame on that line, if any, is still read
+            (Some(Encoding::Binary), JSON_DELIMITER) if self.is_text() => {
+                return self.take_text_line();
+            }
+            (Some(Encoding::Binary), _) => {}
+            (_, JSON_DELIMITER) => {
+                if self.encoding.is_none() {
+                    self.discarding = false;
+                    return None;
+                }
+                return self.take(self.json_encoding());
+            }
+            (None, b'{') if !self.discarding => self.encoding = Some(Encoding::Json),
+            (None, byte) if byte.is_ascii_whitespace() => return None,
+            (None, _) => {
+                self.discarding = true;
+                return None;
+            }
+            _ => {}
+        }
+
+        if !self.discarding
+            && (self.buffer.as_slice().len() >= MAX_FRAME_LENGTH || !self.buffer.push(byte))
+        {
+            // the rest of the frame is skipped as noise, whatever its encoding
+            self.buffer.clear();
+            self.encoding = None;
+            self.discarding = true;
+        }
+        None
+    }
+
+    /// A frame has a checksum if it ends in `}*XXXX`. Plain JSON never does, it ends in `}`
+    fn json_encoding(&self) -> Encoding {
+        let json = self.buffer.as_slice().trim_ascii_end();
+        let Some(split) = json.len().checked_sub(JSON_CHECKSUM_LENGTH + 1) else {
+            return Encoding::Json;
+        };
+        match &json[split..] {
+            [b'}', JSON_CHECKSUM_MARKER, digits @ ..]
+                if digits.iter().all(u8::is_ascii_hexdigit) =>
+            {
+                Encoding::JsonChecksum
+            }
+            _ => Encoding::Json,
+        }
+    }
+
+    /// Whether the binary frame being read is a text line instead. Binary frames never are:
+    /// one of their first three bytes (COBS code, ID, variant or `Option` tag) is a control byte
+    /// or above 0x7F
+    fn is_text(&self) -> bool {
+        let bytes = self.buffer.as_slice();
+        bytes.len() >= 3
+            && bytes
+                .iter()
+                .all(|&byte| byte.is_ascii_graphic() || byte.is_ascii_whitespace())
+    }
+
+    /// Ends binary mode on the line just read, returning the JSON frame at its end if there is one
+    fn take_text_line(&mut self) -> Option<Encoding> {
+        let bytes = self.buffer.as_slice();
+        let line = bytes
+            .iter()
+            .rposition(|&byte| byte == JSON_DELIMITER)
+            .map_or(0, |position| position + 1);
+        let start = bytes[line..]
+            .iter()
+            .position(|byte| !byte.is_ascii_whitespace())
+            .map_or(bytes.len(), |position| line + position);
+        if bytes.get(start) != Some(&b'{') {
+            self.encoding = None;
+            self.buffer.clear();
+            return None;
+        }
+        self.buffer.remove_front(start);
+        self.take(self.json_encoding())
+    }
+
+    fn take(&mut self, encoding: Encoding) -> Option<Encoding> {
+        self.encoding = None;
+        if mem::take(&mut self.discarding) {
+            self.buffer.clear();
+            return None;
+        }
+        if encoding != Encoding::Binary {
+            // tolerate CRLF from terminals
+            let length = self.buffer.as_slice().trim_ascii_end().len();
+            self.buffer.truncate(length);
+        }
+        self.complete = true;
+        Some(encoding)
+    }
+}
+
+/// Splits a byte stream into JSON and binary frames
+#[cfg(feature = "alloc")]
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    splitter: Splitter<Vec<u8>>,
+}
+
+#[cfg(feature = "alloc")]
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds one byte, returns the frame it completes
+    pub fn push(&mut self, byte: u8) -> Option<(Encoding, Vec<u8>)> {
+        let encoding = self.splitter.push(byte)?;
+        Some((encoding, mem::take(&mut self.splitter.buffer)))
+    }
+}
+
+/// [`FrameDecoder`] with a fixed buffer of `N` bytes, longer frames are dropped
+#[derive(Debug, Default)]
+pub struct FixedFrameDecoder<const N: usize> {
+    splitter: Splitter<heapless::Vec<u8, N>>,
+}
+
+impl<const N: usize> FixedFrameDecoder<N> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds one byte, returns the frame it completes. It stays valid until the next byte,
+    /// [`decode_in_place`] may overwrite it
+    pub fn push(&mut self, byte: u8) -> Option<(Encoding, &mut [u8])> {
+        let encoding = self.splitter.push(byte)?;
+        Some((encoding, &mut self.splitter.buffer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ParameterTypes, RGBLedColor, Request, RequestFrame, Response};
+    use alloc::{boxed::Box, string::String};
+    use proptest::prelude::*;
+
+    fn requests() -> Vec<RequestFrame> {
+        [
+            Request::Hello,
+            Request::SetEffect(3),
+            Request::SetOption(
+                "color".into(),
+                ParameterTypes::Color(RGBLedColor::new(1, 0, 3)),
+            ),
+            Request::SetOption("name".into(), ParameterTypes::Text("\"desk\"\n\0".into())),
+            Request::InZone(1, Box::new(Request::SetBrightness(0.5))),
+            Request::StreamColor {
+                color: RGBLedColor::new(0, 0, 0),
+                timeout_ms: 0,
+            },
+        ]
+        .into_iter()
+        .zip(1..)
+        .map(|(request, id)| RequestFrame { id, request })
+        .collect()
+    }
+
+    /// Requests don't implement `PartialEq`, their JSON does
+    fn json<T: Serialize>(message: &T) -> String {
+        serde_json::to_string(message).unwrap()
+    }
+
+    /// Frames completed by feeding `stream` byte by byte
+    fn split(decoder: &mut FrameDecoder, stream: &[u8]) -> Vec<(Encoding, Vec<u8>)> {
+        stream
+            .iter()
+            .filter_map(|&byte| decoder.push(byte))
+            .collect()
+    }
+
+    fn round_trip(encoding: Encoding, frame: &RequestFrame) -> RequestFrame {
+        let bytes = encode(encoding, frame);
+        let [(decoded_encoding, ref contents)] = split(&mut FrameDecoder::new(), &bytes)[..] else {
+            panic!("not a single frame: {bytes:?}");
+        };
+        assert_eq!(decoded_encoding, encoding);
+        decode(encoding, contents).unwrap()
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        for encoding in [Encoding::Json, Encoding::Binary] {
+            for frame in requests() {
+                assert_eq!(
+                    json(&round_trip(encoding, &frame)),
+                    json(&frame),
+                    "{encoding:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn json_frames_are_lines() {
+        let frame = encode(
+            Encoding::Json,
+            &RequestFrame {
+                id: 7,
+                request: Request::Hello,
+            },
+        );
+        assert_eq!(frame, b"{\"id\":7,\"request\":\"Hello\"}\n");
+    }
+
+    #[test]
+    fn binary_frames_have_no_inner_zeros() {
+        for frame in requests() {
+            let bytes = encode(Encoding::Binary, &frame);
+            assert_eq!(bytes.first(), Some(&BINARY_DELIMITER));
+            assert_eq!(bytes.last(), Some(&BINARY_DELIMITER));
+            assert!(!bytes[1..bytes.len() - 1].contains(&BINARY_DELIMITER));
+        }
+    }
+
+    #[test]
+    fn fixed_encoding_matches() {
+        // the firmware writes replies with it, serde-json-core has no tuple variants for requests
+        let replies = [
+            Response::new(Some(1), Ok(String::from("hue_rotate"))),
+            Response::new(None, Ok(String::new())),
+            Response::new(
+                Some(u32::MAX),
+                Err(crate::Error::new(ErrorCode::UnknownEffect, "no \"effect\"")),
+            ),
+        ];
+        let mut buffer = [0; 256];
+        for encoding in [Encoding::Json, Encoding::JsonChecksum, Encoding::Binary] {
+            for reply in &replies {
+                let bytes = encode_into(encoding, reply, &mut buffer).unwrap();
+                assert_eq!(bytes, &encode(encoding, reply)[..], "{encoding:?}");
+            }
+            assert!(encode_into(encoding, &replies[0], &mut [0; 8]).is_none());
+        }
+    }
+
+    #[test]
+    fn interleaved_encodings_share_a_stream() {
+        let frames = requests();
+        let encodings = [
+            Encoding::Json,
+            Encoding::Binary,
+            Encoding::Binary,
+            Encoding::Json,
+        ];
+        let mut stream = Vec::new();
+        for (frame, encoding) in frames.iter().zip(encodings.iter().cycle()) {
+            stream.extend(encode(*encoding, frame));
+        }
+        let reply = Response::new(Some(1), Ok::<_, crate::Error>(String::from("hue_rotate")));
+        stream.extend(encode(Encoding::Binary, &reply));
+        stream.extend(encode(Encoding::Json, &reply));
+
+        let split = split(&mut FrameDecoder::new(), &stream);
+        assert_eq!(split.len(), frames.len() + 2);
+        for ((encoding, contents), (frame, expected)) in split
+            .iter()
+            .zip(frames.iter().zip(encodings.iter().cycle()))
+        {
+            assert_eq!(encoding, expected);
+            let decoded: RequestFrame = decode(*encoding, contents).unwrap();
+            assert_eq!(json(&decoded), json(frame));
+        }
+        for (encoding, contents) in &split[frames.len()..] {
+            let decoded: Response<String> = decode(*encoding, contents).unwrap();
+            assert_eq!(decoded.result.unwrap(), "hue_rotate");
+        }
+    }
+
+    #[test]
+    fn stray_zero_doesnt_swallow_text() {
+        let frames = requests();
+        let mut stream = vec![BINARY_DELIMITER];
+        stream.extend(b"garbage after a reset\r\n");
+        for frame in &frames[..3] {
+            stream.extend(encode(Encoding::Json, frame));
+        }
+        // right before a frame, the frame is still read
+        stream.push(BINARY_DELIMITER);
+        stream.extend(encode(Encoding::Json, &frames[3]));
+        stream.extend(encode(Encoding::Binary, &frames[4]));
+
+        let split = split(&mut FrameDecoder::new(), &stream);
+        let encodings: Vec<_> = split.iter().map(|(encoding, _)| *encoding).collect();
+        assert_eq!(
+            encodings,
+            [
+                Encoding::Json,
+                Encoding::Json,
+                Encoding::Json,
+                Encoding::Json,
+                Encoding::Binary
+            ]
+        );
+        for ((encoding, contents), frame) in split.iter().zip(&frames) {
+            let decoded: RequestFrame = decode(*encoding, contents).unwrap();
+            assert_eq!(json(&decoded), json(frame));
+        }
+    }
+
+    fn corrupt_error(encoding: Encoding, contents: &[u8]) -> ErrorCode {
+        decode::<RequestFrame>(encoding, contents).unwrap_err().code
+    }
+
+    #[test]
+    fn json_checksums_are_verified() {
+        let frame = &requests()[2];
+        let bytes = encode(Encoding::JsonChecksum, frame);
+        let [(encoding, ref contents)] = split(&mut FrameDecoder::new(), &bytes)[..] else {
+            panic!("not a single frame");
+        };
+        assert_eq!(encoding, Encoding::JsonChecksum);
+        let decoded: RequestFrame = decode(encoding, contents).unwrap();
+        assert_eq!(json(&decoded), json(frame));
+
+        // a flipped digit in the JSON, and in the checksum itself
+        let mut corrupted = contents.clone();
+        corrupted[10] ^= 0x01;
+        assert_eq!(corrupt_error(encoding, &corrupted), ErrorCode::CorruptFrame);
+        let mut corrupted = contents.clone();
+        *corrupted.last_mut().unwrap() ^= 0x01;
+        assert_eq!(corrupt_error(encoding, &corrupted), ErrorCode::CorruptFrame);
+        assert_eq!(
+            corrupt_error(encoding, &contents[..contents.len() - 1]),
+            ErrorCode::CorruptFrame
+        );
+    }
+
+    #[test]
+    fn json_checksums_are_optional() {
+        let frame = &requests()[2];
+        let bytes = encode(Encoding::Json, frame);
+        let [(encoding, ref contents)] = split(&mut FrameDecoder::new(), &bytes)[..] else {
+            panic!("not a single frame");
+        };
+        assert_eq!(encoding, Encoding::Json);
+        assert!(!contents.contains(&JSON_CHECKSUM_MARKER));
+        assert_eq!(
+            json(&decode::<RequestFrame>(encoding, contents).unwrap()),
+            json(frame)
+        );
+    }
+
+    #[test]
+    fn text_ending_like_a_checksum_is_plain_json() {
+        for text in ["*a", "*abcd", "x}*abcd"] {
+            let frame = RequestFrame {
+                id: 7,
+                request: Request::SetOption("name".into(), ParameterTypes::Text(text.into())),
+            };
+            let bytes = encode(Encoding::Json, &frame);
+            let [(encoding, ref contents)] = split(&mut FrameDecoder::new(), &bytes)[..] else {
+                panic!("not a single frame");
+            };
+            assert_eq!(encoding, Encoding::Json, "{text}");
+            assert_eq!(
+                json(&decode::<RequestFrame>(encoding, contents).unwrap()),
+                json(&frame)
+            );
+        }
+    }
+
+    #[test]
+    fn binary_checksums_are_verified() {
+        let bytes = encode(Encoding::Binary, &requests()[2]);
+        let contents = &bytes[1..bytes.len() - 1];
+        for index in 0..contents.len() {
+            let mut corrupted = contents.to_vec();
+            // keep it free of zeros, those would end the frame early
+            corrupted[index] = if corrupted[index] == 0xFF {
+                0x01
+            } else {
+                corrupted[index] + 1
+            };
+            let code = corrupt_error(Encoding::Binary, &corrupted);
+            assert_eq!(code, ErrorCode::CorruptFrame, "byte {index}");
+        }
+    }
+
+    #[test]
+    fn noise_before_frames_is_skipped() {
+        let frames = requests();
+        let mut stream =
+            b"ESP-ROM:esp32c3-api1-20210207\r\nrst:0x1 (POWERON),boot:0xc\r\n\r\n".to_vec();
+        stream.extend(encode(Encoding::Json, &frames[0]));
+        stream.extend(b"I (312) espled: started\n}{\n  \t");
+        stream.extend(encode(Encoding::Binary, &frames[1]));
+        stream.extend(b"\xff\xfe noise\n");
+        stream.extend(encode(Encoding::JsonChecksum, &frames[2]));
+
+        let split = split(&mut FrameDecoder::new(), &stream);
+        let encodings: Vec<_> = split.iter().map(|(encoding, _)| *encoding).collect();
+        assert_eq!(
+            encodings,
+            [Encoding::Json, Encoding::Binary, Encoding::JsonChecksum]
+        );
+        for ((encoding, contents), frame) in split.iter().zip(&frames) {
+            let decoded: RequestFrame = decode(*encoding, contents).unwrap();
+            assert_eq!(json(&decoded), json(frame));
+        }
+    }
+
+    #[test]
+    fn crlf_endings_are_tolerated() {
+        let frame = &requests()[1];
+        for encoding in [Encoding::Json, Encoding::JsonChecksum] {
+            let mut bytes = encode(encoding, frame);
+            bytes.insert(bytes.len() - 1, b'\r');
+            let [(decoded_encoding, ref contents)] = split(&mut FrameDecoder::new(), &bytes)[..]
+            else {
+                panic!("not a single frame");
+            };
+            assert_eq!(decoded_encoding, encoding);
+            let decoded: RequestFrame = decode(encoding, contents).unwrap();
+            assert_eq!(json(&decoded), json(frame));
+        }
+    }
+
+    #[test]
+    fn oversized_frames_are_dropped() {
+        let long = RequestFrame {
+            id: 1,
+            request: Request::SetOption(
+                "name".into(),
+                ParameterTypes::Text("x".repeat(MAX_FRAME_LENGTH)),
+            ),
+        };
+        let frames = requests();
+        for encoding in [Encoding::Json, Encoding::Binary] {
+            let mut stream = encode(encoding, &long);
+            stream.extend(encode(Encoding::Json, &frames[0]));
+            stream.extend(encode(encoding, &long));
+            stream.extend(encode(Encoding::Binary, &frames[1]));
+
+            let split = split(&mut FrameDecoder::new(), &stream);
+            assert_eq!(split.len(), 2, "{encoding:?}");
+            for ((encoding, contents), frame) in split.iter().zip(&frames) {
+                let decoded: RequestFrame = decode(*encoding, contents).unwrap();
+                assert_eq!(json(&decoded), json(frame));
+            }
+        }
+    }
+
+    #[test]
+    fn fixed_decoder_recovers_from_overflow() {
+        let frames = requests();
+        let mut decoder = FixedFrameDecoder::<12>::new();
+        let mut stream = encode(Encoding::Binary, &frames[2]);
+        stream.extend(encode(Encoding::Json, &frames[2]));
+        stream.extend(encode(Encoding::Binary, &frames[0]));
+        stream.extend(b"{\"id\":2}\n");
+
+        let mut split = Vec::new();
+        for byte in stream {
+            if let Some((encoding, contents)) = decoder.push(byte) {
+                split.push((encoding, contents.to_vec()));
+            }
+        }
+        assert_eq!(split.len(), 2);
+        assert_eq!(split[0].0, Encoding::Binary);
+        let decoded: RequestFrame = decode(Encoding::Binary, &split[0].1).unwrap();
+        assert_eq!(json(&decoded), json(&frames[0]));
+        assert_eq!(split[1], (Encoding::Json, b"{\"id\":2}".to_vec()));
+    }
+
+    proptest! {
+        #[test]
+        fn any_text_round_trips(name in "(?s).*", text in "(?s).*", id in any::<u32>()) {
+            let frame = RequestFrame {
+                id,
+                request: Request::SetOption(name, ParameterTypes::Text(text)),
+            };
+            for encoding in [Encoding::Json, Encoding::Binary] {
+                prop_assert_eq!(json(&round_trip(encoding, &frame)), json(&frame));
+            }
+        }
+    }
+}
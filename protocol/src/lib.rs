@@ -1,119 +1,512 @@
+//! Messages between espled controllers and their clients.
+//!
+//! The crate is `no_std`. The default `std` feature only forwards to the dependencies, `alloc`
+//! enables the messages with `String`, `Vec` and map payloads, the [`pixels`] frames and the JSON
+//! support of [`framing::encode`]/[`framing::decode`]. Without a heap, [`fixed`] has `heapless` counterparts
+//! of the messages a controller receives. `client` (default) adds a blocking client, `serial` its
+//! serial port transport, `schema` a JSON Schema of the JSON frames.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "alloc")]
+use alloc::{boxed::Box, collections::BTreeMap, string::String, vec::Vec};
+use core::fmt;
 use serde::{Deserialize, Serialize};
 
+pub mod blend;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod color;
+#[cfg(feature = "alloc")]
+pub mod command;
+mod css_colors;
+pub mod device;
+pub mod event;
+pub mod fixed;
+pub mod framing;
+pub mod pairing;
+pub mod palette;
+#[cfg(feature = "alloc")]
+pub mod pixels;
+#[cfg(feature = "schema")]
+pub mod schema;
+pub mod white;
+pub mod zone;
+
+pub use color::RGBLedColor;
+#[cfg(feature = "alloc")]
+pub use device::DeviceInfo;
+pub use device::{MacAddress, NvsUsage, PowerState, ResetReason};
+#[cfg(feature = "alloc")]
+pub use event::Event;
+pub use event::EventKind;
+pub use pairing::Token;
+#[cfg(feature = "alloc")]
+pub use palette::Palette;
+pub use palette::{ColorStop, Interpolation};
+#[cfg(feature = "alloc")]
+pub use pixels::PixelFrame;
+pub use white::{RGBWLedColor, RGBWWLedColor};
+#[cfg(feature = "alloc")]
+pub use zone::ZoneInfo;
+pub use zone::{ChannelLayout, ZoneId, DEFAULT_ZONE};
+
 
 pub const DEFAULT_GAMMA_COEFICIENT: f32 = 2.2;
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+/// Version of the wire protocol. Bump `minor` when adding requests or capabilities, `major` on breaking changes
+pub const PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 0 };
+
+#[cfg(feature = "alloc")]
+/// New types are appended to the end, so the binary encoding of the existing ones stays the same
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum ParameterTypes {
-    Color(RGBLedColor),
+    Color(#[serde(deserialize_with = "color::hex::deserialize")] RGBLedColor),
     Float(f32),
+    Int(i32),
+    Bool(bool),
+    /// One of `options`, `selected` is an index into it
+    Choice {
+        selected: usize,
+        options: Vec<String>,
+    },
+    Text(String),
+    Palette(Palette),
+    /// Colour temperature, turned into a colour with [`RGBLedColor::from_kelvin`]
+    Kelvin(u16),
 }
 
+#[cfg(feature = "alloc")]
 impl ParameterTypes {
-    pub fn as_f32(self) -> Option<f32> {
+    /// Returns `true` if both values are the same variant, regardless of the payload
+    pub fn same_type(&self, other: &ParameterTypes) -> bool {
+        core::mem::discriminant(self) == core::mem::discriminant(other)
+    }
+
+    pub fn as_f32(&self) -> Option<f32> {
         match self {
-            ParameterTypes::Color(_) => {
-                None
-            },
-            ParameterTypes::Float(value) => Some(value),
+            ParameterTypes::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_i32(&self) -> Option<i32> {
+        match self {
+            ParameterTypes::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            ParameterTypes::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            ParameterTypes::Text(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Selected option of a [`ParameterTypes::Choice`]
+    pub fn as_choice(&self) -> Option<&str> {
+        match self {
+            ParameterTypes::Choice { selected, options } => {
+                options.get(*selected).map(String::as_str)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn as_palette(&self) -> Option<&Palette> {
+        match self {
+            ParameterTypes::Palette(palette) => Some(palette),
+            _ => None,
+        }
+    }
+
+    pub fn as_kelvin(&self) -> Option<u16> {
+        match self {
+            ParameterTypes::Kelvin(value) => Some(*value),
+            _ => None,
         }
     }
 }
 
 
+#[cfg(feature = "alloc")]
+/// Describes one effect parameter and carries its current value
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ParameterDescriptor {
+    pub key: String,
+    pub label: String,
+    pub value: ParameterTypes,
+    pub default: ParameterTypes,
+    /// Range of `Float`, `Int` and `Kelvin` values
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+    pub step: Option<f32>,
+    pub unit: Option<String>,
+    pub group: Option<String>,
+}
+
+#[cfg(feature = "alloc")]
+impl ParameterDescriptor {
+    pub fn new<K: Into<String>, L: Into<String>>(key: K, label: L, default: ParameterTypes) -> Self {
+        Self {
+            key: key.into(),
+            label: label.into(),
+            value: default.clone(),
+            default,
+            min: None,
+            max: None,
+            step: None,
+            unit: None,
+            group: None,
+        }
+    }
+
+    pub fn range(mut self, min: f32, max: f32) -> Self {
+        self.min = Some(min);
+        self.max = Some(max);
+        self
+    }
+
+    pub fn step(mut self, step: f32) -> Self {
+        self.step = Some(step);
+        self
+    }
+
+    pub fn unit<S: Into<String>>(mut self, unit: S) -> Self {
+        self.unit = Some(unit.into());
+        self
+    }
+
+    pub fn group<S: Into<String>>(mut self, group: S) -> Self {
+        self.group = Some(group.into());
+        self
+    }
+
+    /// Clamps `Float`, `Int` and `Kelvin` values to the declared range, other types are returned as is.
+    /// `Kelvin` is also kept within [`color::MIN_KELVIN`]..=[`color::MAX_KELVIN`]
+    pub fn clamp(&self, value: ParameterTypes) -> ParameterTypes {
+        match value {
+            ParameterTypes::Float(mut value) => {
+                if let Some(min) = self.min {
+                    value = value.max(min);
+                }
+                if let Some(max) = self.max {
+                    value = value.min(max);
+                }
+                ParameterTypes::Float(value)
+            }
+            ParameterTypes::Int(mut value) => {
+                if let Some(min) = self.min {
+                    value = value.max(libm::ceilf(min) as i32);
+                }
+                if let Some(max) = self.max {
+                    value = value.min(libm::floorf(max) as i32);
+                }
+                ParameterTypes::Int(value)
+            }
+            ParameterTypes::Kelvin(mut value) => {
+                if let Some(min) = self.min {
+                    value = value.max(libm::ceilf(min) as u16);
+                }
+                if let Some(max) = self.max {
+                    value = value.min(libm::floorf(max) as u16);
+                }
+                ParameterTypes::Kelvin(value.clamp(color::MIN_KELVIN, color::MAX_KELVIN))
+            }
+            value => value,
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+/// New requests are appended to the end, so the binary encoding of the existing ones stays the same
 #[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum Request {
+    /// Handshake, answered with [`VersionInfo`]. Always sent as JSON, so unknown capabilities of newer peers can be skipped
+    Hello,
+    /// Answered with the effect names, in firmware order
     GetEffects,
+    /// Answered with the name of the current effect
     GetEffect,
+    /// Answered with the ordered [`ParameterDescriptor`] list of the current effect
     GetParameters,
     GetName,
+    /// Index into [`Request::GetEffects`]. It depends on the effect order of the firmware,
+    /// prefer [`Request::SetEffectById`]
     SetEffect(usize),
+    /// Answered with the value actually applied, after clamping to the parameter range
     SetOption(String, ParameterTypes),
+    /// Sets several parameters of the current effect at once and persists them once.
+    /// Either every value is applied or none is, answered with the result of each key,
+    /// see [`ErrorCode::Aborted`]
+    SetOptions(BTreeMap<String, ParameterTypes>),
+    /// Replaces the event subscription of this link, an empty list ends it. See [`event`]
+    Subscribe(Vec<EventKind>),
+    /// Shows `color` right away, bypassing the current effect, for ambilight, music sync and the like.
+    /// Nothing is stored and the request is not answered, so clients can send at frame rate.
+    /// When no frame arrives for `timeout_ms` the controller goes back to the stored effect.
+    ///
+    /// A binary frame is about 13 bytes, ~1.2 ms at 115200 baud. The firmware polls the link every
+    /// 5 ms when idle and updates the PWM duty as soon as a frame is read, so expect well under 10 ms
+    /// from write to light, enough for 100 Hz.
+    StreamColor {
+        #[serde(deserialize_with = "color::hex::deserialize")]
+        color: RGBLedColor,
+        timeout_ms: u32,
+    },
+    /// Answered with [`DeviceInfo`]
+    GetDeviceInfo,
+    /// Turns the output on or off with a short fade, the effect keeps running underneath.
+    /// Persisted, answered with the new state
+    SetPower(bool),
+    /// Master brightness in [0.0, 1.0], applied on top of the effect. Persisted, answered with
+    /// the value actually applied
+    SetBrightness(f32),
+    /// Answered with [`PowerState`]
+    GetPowerState,
+    /// Answered with an [`EffectInfo`] per effect, in the order of [`Request::GetEffects`]
+    ListEffects,
+    /// Selects the effect with this [`EffectInfo::id`], answered like [`Request::SetEffect`]
+    SetEffectById(String),
+    /// Answered with the [`EffectInfo::id`] of the current effect
+    GetEffectId,
+    /// Answered with a [`ZoneInfo`] per zone
+    GetZones,
+    /// Sends the wrapped request to one zone, answered like the wrapped request. Unknown zones
+    /// are answered with [`ErrorCode::UnknownZone`], requests that don't
+    /// [accept a zone](Request::accepts_zone) with [`ErrorCode::MalformedRequest`]. See [`zone`]
+    InZone(ZoneId, Box<Request>),
+    /// Asks the controller to show a pairing code by blinking the LEDs. The code itself is never
+    /// sent over a link, the reply is empty. Refused with [`ErrorCode::Unauthorized`] while another code is pending or pairing is
+    /// locked after wrong codes. See [`pairing`]
+    StartPairing,
+    /// Trades the code shown after [`Request::StartPairing`] for a new [`Token`]. Codes are
+    /// single use and expire, wrong ones are answered with [`ErrorCode::Unauthorized`]
+    Pair(u32),
+    /// Carries the token of a paired client, answered like the wrapped request. Required on
+    /// network links, see [`pairing`]. Doesn't nest, an inner one is answered with
+    /// [`ErrorCode::MalformedRequest`]
+    Authorized(Token, Box<Request>),
 }
 
+#[cfg(feature = "alloc")]
+impl Request {
+    /// Effect, parameter and colour stream requests, the ones [`Request::InZone`] may wrap
+    pub fn accepts_zone(&self) -> bool {
+        matches!(
+            self,
+            Request::GetEffects
+                | Request::GetEffect
+                | Request::GetParameters
+                | Request::SetEffect(_)
+                | Request::SetOption(..)
+                | Request::SetOptions(_)
+                | Request::StreamColor { .. }
+                | Request::ListEffects
+                | Request::SetEffectById(_)
+                | Request::GetEffectId
+        )
+    }
 
-#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize)]
-pub struct RGBLedColor {
-    pub red: u8,
-    pub green: u8,
-    pub blue: u8,
-}
-
-impl RGBLedColor {
-    pub fn new(red: u8, green: u8, blue: u8) -> Self {
-        Self { red, green, blue }
+    /// Everything but the handshake and pairing, a network link answers these with
+    /// [`ErrorCode::Unauthorized`] unless they come wrapped in [`Request::Authorized`].
+    /// See [`pairing`]
+    pub fn needs_token(&self) -> bool {
+        !matches!(
+            self,
+            Request::Hello | Request::StartPairing | Request::Pair(_) | Request::Authorized(..)
+        )
     }
-    /// H - [0.0, 360.0], S - [0.0, 1.0], V - [0.0, 1.0]
-    pub fn from_hsv(h: f32, s: f32, v: f32) -> Self {
-        let c = v * s; // chroma
-        let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
-        let m = v - c;
+}
 
-        let (r1, g1, b1) = match h {
-            0.0..=60.0 => (c, x, 0.0),
-            60.0..=120.0 => (x, c, 0.0),
-            120.0..=180.0 => (0.0, c, x),
-            180.0..=240.0 => (0.0, x, c),
-            240.0..=300.0 => (x, 0.0, c),
-            300.0..=360.0 => (c, 0.0, x),
-            _ => (0.0, 0.0, 0.0),
-        };
+#[cfg(feature = "alloc")]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct EffectInfo {
+    /// Stays the same across firmware versions, unlike the position of the effect
+    pub id: String,
+    /// Shown to the user
+    pub name: String,
+}
 
-        let red = ((r1 + m) * 255.0).round() as u8;
-        let green = ((g1 + m) * 255.0).round() as u8;
-        let blue = ((b1 + m) * 255.0).round() as u8;
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ProtocolVersion {
+    pub major: u16,
+    pub minor: u16,
+}
 
-        Self { red, green, blue }
+impl ProtocolVersion {
+    /// Peers can talk to each other when the major versions match. A lower minor version means some requests are missing
+    pub fn is_compatible_with(&self, other: &ProtocolVersion) -> bool {
+        self.major == other.major
     }
+}
 
-    pub fn new_from_u32(color: u32) -> Self {
-        Self {
-            red: ((color >> 16) & 0xFF) as u8,
-            green: ((color >> 8) & 0xFF) as u8,
-            blue: (color & 0xFF) as u8,
-        }
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
     }
+}
 
-    pub fn gamma_correct(&mut self, coeficient: f32) {
-        self.red = ((self.red as f32 / 255.0).powf(coeficient) * 255.0).round() as u8;
-        self.green = ((self.green as f32 / 255.0).powf(coeficient) * 255.0).round() as u8;
-        self.blue = ((self.blue as f32 / 255.0).powf(coeficient) * 255.0).round() as u8;
-    }
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Capability {
+    ColorParameter,
+    FloatParameter,
+    SerialTransport,
+    /// Accepts [`framing::Encoding::Binary`] frames, which carry a CRC-16
+    BinaryFraming,
+    /// Verifies the checksums of [`framing::Encoding::JsonChecksum`] frames
+    FrameChecksum,
+    IntParameter,
+    BoolParameter,
+    ChoiceParameter,
+    TextParameter,
+    PaletteParameter,
+    KelvinParameter,
+    /// Accepts colours in JSON frames as any text form of [`RGBLedColor::from_str`](core::str::FromStr)
+    ColorStrings,
+    /// Handles [`Request::SetOptions`]
+    BatchParameters,
+    /// Handles [`Request::Subscribe`] and pushes [`Event`]s
+    Events,
+    /// Handles [`Request::StreamColor`]
+    ColorStreaming,
+    /// Handles [`Request::GetDeviceInfo`]
+    DeviceInfo,
+    /// Handles [`Request::SetPower`], [`Request::SetBrightness`] and [`Request::GetPowerState`]
+    PowerControl,
+    /// Handles [`Request::ListEffects`], [`Request::SetEffectById`] and [`Request::GetEffectId`]
+    EffectIds,
+    /// Handles [`Request::GetZones`] and [`Request::InZone`]
+    Zones,
+    /// Handles [`Request::StartPairing`], [`Request::Pair`] and [`Request::Authorized`]. Network
+    /// links of such a controller require a token
+    Pairing,
+    /// Capability added by a newer peer
+    #[serde(other)]
+    #[cfg_attr(feature = "schema", schemars(skip))]
+    Unknown,
+}
 
-    pub fn to_u32(&self) -> u32 {
-        let red = (self.red as u32) << 16;
-        let green = (self.green as u32) << 8;
-        let blue = self.blue as u32;
+#[cfg(feature = "alloc")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct VersionInfo {
+    pub protocol_version: ProtocolVersion,
+    pub firmware_version: String,
+    pub capabilities: Vec<Capability>,
+}
 
-        red | green | blue
+#[cfg(feature = "alloc")]
+impl VersionInfo {
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
     }
 }
 
+#[cfg(feature = "alloc")]
+/// Request with a caller-supplied ID, echoed back in the matching [`Response`]
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RequestFrame {
+    pub id: u32,
+    pub request: Request,
+}
 
-impl Into<[f32; 3]> for RGBLedColor {
-    fn into(self) -> [f32; 3] {
-        [
-            self.red as f32 / 255.0,
-            self.green as f32 / 255.0,
-            self.blue as f32 / 255.0,
-        ]
-    }
+/// Only the ID of a [`RequestFrame`], used to address an error reply when the request itself can't be parsed
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RequestHeader {
+    pub id: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum ErrorCode {
+    UnknownEffect,
+    UnknownParameter,
+    TypeMismatch,
+    StorageFailure,
+    MalformedRequest,
+    /// Frame failed its checksum and was dropped
+    CorruptFrame,
+    /// Value has the right type, but is outside of what the parameter accepts
+    OutOfRange,
+    /// Valid on its own, but not applied because another part of the same request was rejected
+    Aborted,
+    /// The LEDs could not be driven
+    OutputFailure,
+    /// [`Request::InZone`] names a zone the controller doesn't have
+    UnknownZone,
+    /// Request on a network link without a valid [`Token`], a wrong pairing code or a refused
+    /// [`Request::StartPairing`]
+    Unauthorized,
+}
+
+#[cfg(feature = "alloc")]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Error {
+    pub code: ErrorCode,
+    pub message: String,
 }
 
-impl From<[f32; 3]> for RGBLedColor {
-    fn from(arr: [f32; 3]) -> Self {
-        RGBLedColor {
-            red: (arr[0] * 255.0).round() as u8,
-            green: (arr[1] * 255.0).round() as u8,
-            blue: (arr[2] * 255.0).round() as u8,
+#[cfg(feature = "alloc")]
+impl Error {
+    pub fn new<S: Into<String>>(code: ErrorCode, message: S) -> Self {
+        Self {
+            code,
+            message: message.into(),
         }
     }
 }
 
-impl PartialEq for RGBLedColor {
-    fn eq(&self, other: &Self) -> bool {
-        self.to_u32() == other.to_u32()
+#[cfg(feature = "alloc")]
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl core::error::Error for Error {}
+
+#[cfg(feature = "alloc")]
+/// Reply to a [`RequestFrame`]. `id` is `None` only when the request was too malformed to read its ID
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "schema", schemars(rename = "Response_for_{T}"))]
+pub struct Response<T> {
+    pub id: Option<u32>,
+    pub result: Result<T, Error>,
+}
+
+#[cfg(feature = "alloc")]
+impl<T> Response<T> {
+    pub fn new(id: Option<u32>, result: Result<T, Error>) -> Self {
+        Self { id, result }
     }
 }
 
-impl Eq for RGBLedColor {}
+/// Only the ID of a [`Response`], used to find the reply to a request without knowing its payload type
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ResponseHeader {
+    pub id: Option<u32>,
+}
@@ -0,0 +1,256 @@
+//! Pairing of clients on network links, e.g. TCP.
+//!
+//! A client asks for a code with [`Request::StartPairing`](crate::Request::StartPairing), the
+//! controller shows it to whoever stands next to it and the client trades it for a [`Token`]
+//! with [`Request::Pair`](crate::Request::Pair). From then on it wraps its requests in
+//! [`Request::Authorized`](crate::Request::Authorized). On a network link everything but those
+//! three and [`Request::Hello`](crate::Request::Hello) is answered with
+//! [`ErrorCode::Unauthorized`](crate::ErrorCode::Unauthorized) without a valid token, see
+//! [`Request::needs_token`](crate::Request::needs_token). Serial links are trusted, requests on
+//! them don't need one. See
+//! [`Capability::Pairing`](crate::Capability::Pairing).
+//!
+//! One code is pending at a time, another `StartPairing` is refused until it is used or expires.
+//! [`CODE_ATTEMPTS`] wrong codes in a row lock pairing for [`LOCKOUT_MS`], restarting it doesn't
+//! reset the count.
+
+#[cfg(feature = "alloc")]
+use crate::{Error, ErrorCode, Request};
+use core::{fmt, str::FromStr};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Decimal digits of a pairing code, shown with leading zeros
+pub const CODE_DIGITS: u32 = 6;
+/// Pairing codes are below this
+pub const CODE_LIMIT: u32 = 10u32.pow(CODE_DIGITS);
+/// How long a code can be traded for a token
+pub const CODE_TIMEOUT_MS: u32 = 120_000;
+/// Wrong codes in a row before the controller drops the pending one and locks pairing
+pub const CODE_ATTEMPTS: u8 = 3;
+/// How long pairing is locked after [`CODE_ATTEMPTS`] wrong codes. Every further lockout doubles
+/// it, up to [`MAX_LOCKOUT_MS`], until a client pairs
+pub const LOCKOUT_MS: u32 = 30_000;
+pub const MAX_LOCKOUT_MS: u32 = 3_600_000;
+
+/// Issued per client by [`Request::Pair`](crate::Request::Pair). Lower case hex in JSON frames,
+/// 16 bytes in binary ones. Keep it secret, it is all a client needs
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Token(pub [u8; 16]);
+
+impl Token {
+    /// Compares in constant time, so the time a controller takes to reject a token tells nothing
+    /// about how much of it was right
+    pub fn matches(&self, other: &Token) -> bool {
+        self.0
+            .iter()
+            .zip(other.0.iter())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+    }
+}
+
+/// Lower case hex
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Hides the token, so it doesn't end up in logs
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Token(..)")
+    }
+}
+
+/// Gate a controller puts in front of its requests. Unwraps [`Request::Authorized`] if
+/// `is_authorized` accepts its token and lets everything else through on a `trusted` link or if
+/// it doesn't [need a token](Request::needs_token). The rest is refused with
+/// [`ErrorCode::Unauthorized`]
+#[cfg(feature = "alloc")]
+pub fn authorize(
+    request: Request,
+    trusted: bool,
+    is_authorized: impl FnOnce(&Token) -> bool,
+) -> Result<Request, Error> {
+    match request {
+        Request::Authorized(token, request) => {
+            if is_authorized(&token) {
+                Ok(*request)
+            } else {
+                Err(Error::new(ErrorCode::Unauthorized, "unknown token"))
+            }
+        }
+        request if trusted || !request.needs_token() => Ok(request),
+        _ => Err(Error::new(
+            ErrorCode::Unauthorized,
+            "network links need a token, pair first",
+        )),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseTokenError;
+
+impl fmt::Display for ParseTokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid token, expected 32 hex digits")
+    }
+}
+
+impl core::error::Error for ParseTokenError {}
+
+/// 32 hex digits, ignoring case
+impl FromStr for Token {
+    type Err = ParseTokenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 32 || !s.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(ParseTokenError);
+        }
+        let mut token = [0; 16];
+        for (byte, digits) in token.iter_mut().zip(s.as_bytes().chunks(2)) {
+            // checked above, both are ASCII hex digits
+            let digits = core::str::from_utf8(digits).map_err(|_| ParseTokenError)?;
+            *byte = u8::from_str_radix(digits, 16).map_err(|_| ParseTokenError)?;
+        }
+        Ok(Token(token))
+    }
+}
+
+impl Serialize for Token {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            self.0.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Token {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Hex;
+
+        impl de::Visitor<'_> for Hex {
+            type Value = Token;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("32 hex digits")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Token, E> {
+                value.parse().map_err(E::custom)
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(Hex)
+        } else {
+            <[u8; 16]>::deserialize(deserializer).map(Token)
+        }
+    }
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for Token {
+    fn schema_name() -> alloc::borrow::Cow<'static, str> {
+        "Token".into()
+    }
+
+    fn json_schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        schemars::json_schema!({
+            "type": "string",
+            "pattern": "^[0-9a-fA-F]{32}$",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixed, Request, DEFAULT_ZONE};
+    use alloc::{boxed::Box, format, string::ToString};
+
+    #[test]
+    fn tokens_are_hex_in_json() {
+        let token = Token(core::array::from_fn(|index| index as u8 * 17));
+        let text = "00112233445566778899aabbccddeeff";
+        assert_eq!(token.to_string(), text);
+        assert_eq!(
+            serde_json::to_string(&token).unwrap(),
+            format!("\"{text}\"")
+        );
+        assert_eq!(
+            serde_json::from_str::<Token>(&format!("\"{text}\"")).unwrap(),
+            token
+        );
+        assert_eq!(text.to_uppercase().parse::<Token>(), Ok(token));
+
+        let bytes = postcard::to_allocvec(&token).unwrap();
+        assert_eq!(bytes, token.0);
+        assert_eq!(postcard::from_bytes::<Token>(&bytes).unwrap(), token);
+
+        for invalid in [
+            "",
+            "0011",
+            "g0112233445566778899aabbccddeeff",
+            &format!("{text}00"),
+        ] {
+            assert_eq!(invalid.parse::<Token>(), Err(ParseTokenError));
+        }
+        assert!(!format!("{token:?}").contains("11"));
+    }
+
+    #[test]
+    fn tokens_match_only_themselves() {
+        let token = Token([7; 16]);
+        assert!(token.matches(&Token([7; 16])));
+        let mut other = token;
+        other.0[15] = 8;
+        assert!(!token.matches(&other));
+    }
+
+    #[test]
+    fn only_handshake_and_pairing_go_without_token() {
+        let authorized = Request::Authorized(Token::default(), Box::new(Request::GetName));
+        for request in [Request::Hello, Request::StartPairing, Request::Pair(1), authorized] {
+            assert!(!request.needs_token(), "{request:?}");
+        }
+        let zoned = Request::InZone(DEFAULT_ZONE, Box::new(Request::GetEffect));
+        for request in [Request::GetName, Request::SetPower(false), Request::GetDeviceInfo, zoned] {
+            assert!(request.needs_token(), "{request:?}");
+        }
+
+        let fixed = |json| serde_json::from_str::<fixed::Request>(json).unwrap();
+        assert!(!fixed(r#""Hello""#).needs_token());
+        assert!(!fixed(r#"{"Pair":1}"#).needs_token());
+        assert!(fixed(r#""GetName""#).needs_token());
+        assert!(fixed(r#"{"SetPower":true}"#).needs_token());
+    }
+
+    #[test]
+    fn untrusted_links_need_a_token() {
+        let token = Token([7; 16]);
+        let is_authorized = |candidate: &Token| candidate.matches(&token);
+        let unauthorized = |result: Result<Request, Error>| {
+            result.is_err_and(|error| error.code == ErrorCode::Unauthorized)
+        };
+
+        assert!(unauthorized(authorize(Request::GetName, false, is_authorized)));
+        let wrong = Request::Authorized(Token([8; 16]), Box::new(Request::GetName));
+        assert!(unauthorized(authorize(wrong, false, is_authorized)));
+
+        let right = Request::Authorized(token, Box::new(Request::GetName));
+        assert!(matches!(authorize(right, false, is_authorized), Ok(Request::GetName)));
+        assert!(matches!(authorize(Request::Hello, false, is_authorized), Ok(Request::Hello)));
+        assert!(matches!(authorize(Request::Pair(1), false, is_authorized), Ok(Request::Pair(1))));
+        assert!(matches!(
+            authorize(Request::SetPower(true), true, is_authorized),
+            Ok(Request::SetPower(true))
+        ));
+    }
+}
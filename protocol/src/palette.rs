@@ -0,0 +1,284 @@
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::RGBLedColor;
+
+/// Most stops a palette may have, keeps the stored form small enough for NVS
+pub const MAX_STOPS: usize = 16;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ColorStop {
+    /// Position on the palette, [0.0, 1.0]
+    pub position: f32,
+    #[serde(deserialize_with = "crate::color::hex::deserialize")]
+    pub color: RGBLedColor,
+}
+
+impl ColorStop {
+    pub fn new(position: f32, color: RGBLedColor) -> Self {
+        Self { position, color }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Interpolation {
+    #[default]
+    /// Mixes neighbouring stops in linear RGB, see [`RGBLedColor::lerp`]
+    Linear,
+    /// Holds the color of the previous stop until the next one
+    Stepped,
+}
+
+/// Sorts stops by position, stops at the same position keep their order
+pub(crate) fn sort_stops(stops: &mut [ColorStop]) {
+    // insertion sort, `sort_by` needs `alloc` and palettes are short
+    for index in 1..stops.len() {
+        let mut current = index;
+        while current > 0
+            && stops[current - 1]
+                .position
+                .total_cmp(&stops[current].position)
+                .is_gt()
+        {
+            stops.swap(current - 1, current);
+            current -= 1;
+        }
+    }
+}
+
+/// Colour at `t` on stops sorted by position, shared by [`Palette::sample`] and
+/// [`fixed::Palette::sample`](crate::fixed::Palette::sample)
+pub(crate) fn sample(stops: &[ColorStop], interpolation: Interpolation, t: f32) -> RGBLedColor {
+    // NaN would slip through `clamp` and past both ends
+    let t = if t.is_finite() { t.clamp(0.0, 1.0) } else { 0.0 };
+    let (first, last) = match (stops.first(), stops.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return RGBLedColor::default(),
+    };
+    if t <= first.position {
+        return first.color;
+    }
+    if t >= last.position {
+        return last.color;
+    }
+
+    let next_index = stops
+        .iter()
+        .position(|stop| stop.position > t)
+        .unwrap_or(stops.len() - 1);
+    let previous = stops[next_index - 1];
+    let next = stops[next_index];
+
+    match interpolation {
+        Interpolation::Stepped => previous.color,
+        Interpolation::Linear => {
+            let span = next.position - previous.position;
+            let amount = if span > 0.0 {
+                (t - previous.position) / span
+            } else {
+                0.0
+            };
+            previous.color.lerp(next.color, amount)
+        }
+    }
+}
+
+/// Ordered list of colour stops
+#[cfg(feature = "alloc")]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Palette {
+    pub stops: Vec<ColorStop>,
+    pub interpolation: Interpolation,
+}
+
+#[cfg(feature = "alloc")]
+impl Palette {
+    pub const MAX_STOPS: usize = MAX_STOPS;
+    /// Size of one stop in [`Palette::to_bytes`]: position as `f32` and three colour bytes
+    const STOP_SIZE: usize = 7;
+
+    /// Creates a palette, stops are sorted by position
+    pub fn new(mut stops: Vec<ColorStop>, interpolation: Interpolation) -> Self {
+        sort_stops(&mut stops);
+        Self {
+            stops,
+            interpolation,
+        }
+    }
+
+    /// Colour at `t`, clamped to [0.0, 1.0], non-finite values count as 0.0. Before the first and after
+    /// the last stop their colour is held. An empty palette is black
+    pub fn sample(&self, t: f32) -> RGBLedColor {
+        sample(&self.stops, self.interpolation, t)
+    }
+
+    /// Compact form for storage: interpolation byte, then per stop a little endian `f32` position and RGB bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(1 + self.stops.len() * Self::STOP_SIZE);
+        bytes.push(match self.interpolation {
+            Interpolation::Linear => 0,
+            Interpolation::Stepped => 1,
+        });
+        for stop in self.stops.iter() {
+            bytes.extend(stop.position.to_le_bytes());
+            bytes.extend([stop.color.red, stop.color.green, stop.color.blue]);
+        }
+        bytes
+    }
+
+    /// Reads the form written by [`Palette::to_bytes`]. `None` if the bytes are cut off, have more
+    /// than [`Palette::MAX_STOPS`] stops or a position outside of [0.0, 1.0]
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let (interpolation, stops) = bytes.split_first()?;
+        let interpolation = match interpolation {
+            0 => Interpolation::Linear,
+            1 => Interpolation::Stepped,
+            _ => return None,
+        };
+        if stops.len() % Self::STOP_SIZE != 0 || stops.len() / Self::STOP_SIZE > Self::MAX_STOPS {
+            return None;
+        }
+
+        let stops = stops
+            .chunks_exact(Self::STOP_SIZE)
+            .map(|stop| ColorStop {
+                position: f32::from_le_bytes([stop[0], stop[1], stop[2], stop[3]]),
+                color: RGBLedColor::new(stop[4], stop[5], stop[6]),
+            })
+            .collect::<Vec<_>>();
+        if !stops.iter().all(|stop| (0.0..=1.0).contains(&stop.position)) {
+            return None;
+        }
+        Some(Self::new(stops, interpolation))
+    }
+
+    /// Largest size of [`Palette::to_bytes`]
+    pub const fn max_bytes() -> usize {
+        1 + Self::MAX_STOPS * Self::STOP_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use proptest::prelude::*;
+
+    const RED: RGBLedColor = RGBLedColor::new(255, 0, 0);
+    const BLUE: RGBLedColor = RGBLedColor::new(0, 0, 255);
+
+    fn red_to_blue(interpolation: Interpolation) -> Palette {
+        Palette::new(
+            vec![ColorStop::new(0.25, RED), ColorStop::new(0.75, BLUE)],
+            interpolation,
+        )
+    }
+
+    fn any_palette() -> impl Strategy<Value = Palette> {
+        let stop = (0.0f32..=1.0, any::<(u8, u8, u8)>()).prop_map(|(position, (r, g, b))| {
+            ColorStop::new(position, RGBLedColor::new(r, g, b))
+        });
+        let interpolation = prop_oneof![Just(Interpolation::Linear), Just(Interpolation::Stepped)];
+        (proptest::collection::vec(stop, 0..=MAX_STOPS), interpolation)
+            .prop_map(|(stops, interpolation)| Palette::new(stops, interpolation))
+    }
+
+    #[test]
+    fn empty_palette_is_black() {
+        let palette = Palette::default();
+        for t in [0.0, 0.5, 1.0, f32::NAN] {
+            assert_eq!(palette.sample(t), RGBLedColor::default());
+        }
+    }
+
+    #[test]
+    fn single_stop_is_held() {
+        let palette = Palette::new(vec![ColorStop::new(0.5, RED)], Interpolation::Linear);
+        for t in [0.0, 0.5, 1.0, -1.0, 2.0, f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            assert_eq!(palette.sample(t), RED, "{t}");
+        }
+    }
+
+    #[test]
+    fn ends_are_held() {
+        for interpolation in [Interpolation::Linear, Interpolation::Stepped] {
+            let palette = red_to_blue(interpolation);
+            assert_eq!(palette.sample(0.0), RED);
+            assert_eq!(palette.sample(0.25), RED);
+            assert_eq!(palette.sample(0.75), BLUE);
+            assert_eq!(palette.sample(1.0), BLUE);
+            assert_eq!(palette.sample(-1.0), RED);
+            assert_eq!(palette.sample(2.0), BLUE);
+        }
+    }
+
+    #[test]
+    fn non_finite_positions_sample_the_start() {
+        let palette = red_to_blue(Interpolation::Linear);
+        assert_eq!(palette.sample(f32::NAN), RED);
+        assert_eq!(palette.sample(f32::INFINITY), RED);
+        assert_eq!(palette.sample(f32::NEG_INFINITY), RED);
+    }
+
+    #[test]
+    fn linear_mixes_and_stepped_holds() {
+        let linear = red_to_blue(Interpolation::Linear);
+        assert_eq!(linear.sample(0.5), RED.lerp(BLUE, 0.5));
+        assert_ne!(linear.sample(0.5), RED);
+
+        let stepped = red_to_blue(Interpolation::Stepped);
+        assert_eq!(stepped.sample(0.5), RED);
+        assert_eq!(stepped.sample(0.74), RED);
+    }
+
+    #[test]
+    fn stops_are_sorted() {
+        let palette = Palette::new(
+            vec![ColorStop::new(0.75, BLUE), ColorStop::new(0.25, RED)],
+            Interpolation::Linear,
+        );
+        assert_eq!(palette, red_to_blue(Interpolation::Linear));
+    }
+
+    #[test]
+    fn malformed_bytes_are_rejected() {
+        let bytes = red_to_blue(Interpolation::Stepped).to_bytes();
+        assert_eq!(Palette::from_bytes(&[]), None);
+        // unknown interpolation
+        assert_eq!(Palette::from_bytes(&[2]), None);
+        // cut off in the middle of a stop
+        assert_eq!(Palette::from_bytes(&bytes[..bytes.len() - 1]), None);
+
+        for position in [f32::NAN, -0.5, 1.5, f32::INFINITY] {
+            let mut bytes = bytes.clone();
+            bytes[1..5].copy_from_slice(&position.to_le_bytes());
+            assert_eq!(Palette::from_bytes(&bytes), None, "{position}");
+        }
+
+        let stop = [0, 0, 0, 0, 1, 2, 3];
+        let mut too_long = vec![0];
+        for _ in 0..=MAX_STOPS {
+            too_long.extend(stop);
+        }
+        assert_eq!(Palette::from_bytes(&too_long), None);
+        assert!(Palette::from_bytes(&too_long[..Palette::max_bytes()]).is_some());
+    }
+
+    proptest! {
+        #[test]
+        fn bytes_round_trip(palette in any_palette()) {
+            let bytes = palette.to_bytes();
+            prop_assert!(bytes.len() <= Palette::max_bytes());
+            prop_assert_eq!(Palette::from_bytes(&bytes), Some(palette));
+        }
+
+        #[test]
+        fn sampling_never_panics(palette in any_palette(), t in any::<f32>()) {
+            palette.sample(t);
+        }
+    }
+}
@@ -0,0 +1,365 @@
+//! Frames of addressable strips (WS2812, SK6812), a lot smaller than a list of colours.
+//!
+//! A [`PixelFrame`] carries the bytes of one of three encodings:
+//! - [`PixelFrame::Packed`]: `r g b` per pixel
+//! - [`PixelFrame::RunLength`]: `n r g b` per run of `n + 1` pixels of the same colour
+//! - [`PixelFrame::Delta`]: the changes since the previous frame. The pixel count, then
+//!   `skip count` followed by `count` packed pixels, as often as needed. `skip` counts the
+//!   unchanged pixels since the end of the previous segment, both are LEB128 varints. Pixels
+//!   that no segment covers keep their colour, new ones start black.
+//!
+//! [`PixelFrame::encode`] picks the smallest. The bytes are base64 in JSON frames and as is in
+//! binary ones. Mind [`framing::MAX_FRAME_LENGTH`](crate::framing::MAX_FRAME_LENGTH), packed it
+//! fits about 1360 pixels in a binary frame and 1020 in a JSON one. `cargo bench --bench pixels`
+//! compares the sizes.
+
+use alloc::{string::String, vec::Vec};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{Error, ErrorCode, RGBLedColor};
+
+/// Longest strip a frame may describe, limits what decoding allocates
+pub const MAX_PIXELS: usize = 4096;
+/// Longest run of [`PixelFrame::RunLength`]
+pub const MAX_RUN: usize = 256;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum PixelFrame {
+    Packed(
+        #[serde(with = "bytes")]
+        #[cfg_attr(feature = "schema", schemars(with = "String"))]
+        Vec<u8>,
+    ),
+    RunLength(
+        #[serde(with = "bytes")]
+        #[cfg_attr(feature = "schema", schemars(with = "String"))]
+        Vec<u8>,
+    ),
+    /// Only meaningful to a receiver that has the previous frame
+    Delta(
+        #[serde(with = "bytes")]
+        #[cfg_attr(feature = "schema", schemars(with = "String"))]
+        Vec<u8>,
+    ),
+}
+
+impl PixelFrame {
+    pub fn packed(pixels: &[RGBLedColor]) -> Self {
+        let mut data = Vec::with_capacity(pixels.len() * 3);
+        write_pixels(&mut data, pixels);
+        Self::Packed(data)
+    }
+
+    pub fn run_length(pixels: &[RGBLedColor]) -> Self {
+        let mut data = Vec::new();
+        let mut rest = pixels;
+        while let Some(first) = rest.first() {
+            let length = rest
+                .iter()
+                .take(MAX_RUN)
+                .take_while(|pixel| *pixel == first)
+                .count();
+            data.push((length - 1) as u8);
+            write_pixels(&mut data, &[*first]);
+            rest = &rest[length..];
+        }
+        Self::RunLength(data)
+    }
+
+    /// Changes from `previous` to `pixels`, the two may differ in length
+    pub fn delta(previous: &[RGBLedColor], pixels: &[RGBLedColor]) -> Self {
+        let changed = |index: usize| previous.get(index) != Some(&pixels[index]);
+
+        let mut data = Vec::new();
+        write_varint(&mut data, pixels.len());
+        // a segment header is two bytes or more, so unchanged pixels are never worth sending
+        let mut end = 0;
+        let mut index = 0;
+        while index < pixels.len() {
+            if !changed(index) {
+                index += 1;
+                continue;
+            }
+            let start = index;
+            while index < pixels.len() && changed(index) {
+                index += 1;
+            }
+            write_varint(&mut data, start - end);
+            write_varint(&mut data, index - start);
+            write_pixels(&mut data, &pixels[start..index]);
+            end = index;
+        }
+        Self::Delta(data)
+    }
+
+    /// The smallest encoding of `pixels`, a delta only when the receiver has `previous`
+    pub fn encode(previous: Option<&[RGBLedColor]>, pixels: &[RGBLedColor]) -> Self {
+        let mut candidates = Vec::from([Self::packed(pixels), Self::run_length(pixels)]);
+        if let Some(previous) = previous {
+            candidates.push(Self::delta(previous, pixels));
+        }
+        candidates
+            .into_iter()
+            .min_by_key(|frame| frame.data().len())
+            .expect("there are candidates")
+    }
+
+    /// Encoded bytes, without the serde framing around them
+    pub fn data(&self) -> &[u8] {
+        match self {
+            Self::Packed(data) | Self::RunLength(data) | Self::Delta(data) => data,
+        }
+    }
+
+    /// Pixels of the frame, `previous` is only read by [`PixelFrame::Delta`]
+    pub fn decode(&self, previous: &[RGBLedColor]) -> Result<Vec<RGBLedColor>, Error> {
+        let mut pixels = match self {
+            Self::Delta(_) => previous.to_vec(),
+            _ => Vec::new(),
+        };
+        self.apply(&mut pixels)?;
+        Ok(pixels)
+    }
+
+    /// Like [`PixelFrame::decode`], in place: `pixels` holds the previous frame and is replaced
+    /// by this one. Left as is when the frame is malformed
+    pub fn apply(&self, pixels: &mut Vec<RGBLedColor>) -> Result<(), Error> {
+        match self {
+            Self::Packed(data) => {
+                if data.len() % 3 != 0 {
+                    return Err(malformed("packed pixels are 3 bytes each"));
+                }
+                check_length(data.len() / 3)?;
+                pixels.clear();
+                pixels.extend(data.chunks_exact(3).map(read_pixel));
+            }
+            Self::RunLength(data) => {
+                if data.len() % 4 != 0 {
+                    return Err(malformed("runs are 4 bytes each"));
+                }
+                let runs = data.chunks_exact(4);
+                check_length(runs.clone().map(|run| run[0] as usize + 1).sum())?;
+                pixels.clear();
+                for run in runs {
+                    pixels.resize(pixels.len() + run[0] as usize + 1, read_pixel(&run[1..]));
+                }
+            }
+            Self::Delta(data) => {
+                let mut reader = data.as_slice();
+                let length = read_varint(&mut reader)?;
+                check_length(length)?;
+                // validate everything first, so a malformed frame changes nothing
+                let mut segments = Vec::new();
+                let mut end = 0;
+                while !reader.is_empty() {
+                    let start = end + read_varint(&mut reader)?;
+                    end = start + read_varint(&mut reader)?;
+                    if end > length {
+                        return Err(malformed("segment ends past the last pixel"));
+                    }
+                    let count = (end - start) * 3;
+                    if reader.len() < count {
+                        return Err(malformed("segment is cut short"));
+                    }
+                    let (bytes, rest) = reader.split_at(count);
+                    segments.push((start, bytes));
+                    reader = rest;
+                }
+
+                pixels.resize(length, RGBLedColor::default());
+                for (start, bytes) in segments {
+                    for (pixel, rgb) in pixels[start..].iter_mut().zip(bytes.chunks_exact(3)) {
+                        *pixel = read_pixel(rgb);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn malformed(message: &str) -> Error {
+    Error::new(ErrorCode::MalformedRequest, message)
+}
+
+fn check_length(length: usize) -> Result<(), Error> {
+    if length > MAX_PIXELS {
+        Err(malformed("too many pixels"))
+    } else {
+        Ok(())
+    }
+}
+
+fn write_pixels(data: &mut Vec<u8>, pixels: &[RGBLedColor]) {
+    data.extend(
+        pixels
+            .iter()
+            .flat_map(|pixel| [pixel.red, pixel.green, pixel.blue]),
+    );
+}
+
+fn read_pixel(rgb: &[u8]) -> RGBLedColor {
+    RGBLedColor::new(rgb[0], rgb[1], rgb[2])
+}
+
+fn write_varint(data: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        data.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+/// Three bytes at most, more than [`MAX_PIXELS`] needs
+fn read_varint(data: &mut &[u8]) -> Result<usize, Error> {
+    let mut value = 0;
+    for shift in [0, 7, 14] {
+        let (byte, rest) = data
+            .split_first()
+            .ok_or_else(|| malformed("number is cut short"))?;
+        *data = rest;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(malformed("number is too large"))
+}
+
+/// Base64 in text formats, plain bytes otherwise
+mod bytes {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&STANDARD.encode(data))
+        } else {
+            serializer.serialize_bytes(data)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        if deserializer.is_human_readable() {
+            let text = String::deserialize(deserializer)?;
+            STANDARD.decode(text).map_err(de::Error::custom)
+        } else {
+            Vec::deserialize(deserializer)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framing::{self, Encoding};
+    use alloc::vec;
+    use proptest::prelude::*;
+
+    const BLACK: RGBLedColor = RGBLedColor::new(0, 0, 0);
+    const RED: RGBLedColor = RGBLedColor::new(255, 0, 0);
+    const BLUE: RGBLedColor = RGBLedColor::new(0, 0, 255);
+
+    fn any_pixels() -> impl Strategy<Value = Vec<RGBLedColor>> {
+        // few colours, so there are runs and unchanged pixels
+        let color = prop_oneof![
+            Just(BLACK),
+            Just(RED),
+            Just(BLUE),
+            any::<(u8, u8, u8)>().prop_map(|(red, green, blue)| RGBLedColor::new(red, green, blue))
+        ];
+        prop::collection::vec(color, 0..600)
+    }
+
+    #[test]
+    fn encodings() {
+        let pixels = [RED, RED, RED, BLUE];
+        assert_eq!(
+            PixelFrame::packed(&pixels).data(),
+            [255, 0, 0, 255, 0, 0, 255, 0, 0, 0, 0, 255]
+        );
+        assert_eq!(
+            PixelFrame::run_length(&pixels).data(),
+            [2, 255, 0, 0, 0, 0, 0, 255]
+        );
+        // length 5, skip 1 and change 1, skip 2 and add 1
+        assert_eq!(
+            PixelFrame::delta(&pixels, &[RED, BLUE, RED, BLUE, RED]).data(),
+            [5, 1, 1, 0, 0, 255, 2, 1, 255, 0, 0]
+        );
+        assert_eq!(PixelFrame::delta(&pixels, &pixels[..2]).data(), [2]);
+    }
+
+    #[test]
+    fn long_runs_are_split() {
+        let pixels = vec![RED; MAX_RUN + 1];
+        let frame = PixelFrame::run_length(&pixels);
+        assert_eq!(frame.data(), [255, 255, 0, 0, 0, 255, 0, 0]);
+        assert_eq!(frame.decode(&[]).unwrap(), pixels);
+    }
+
+    #[test]
+    fn bytes_are_base64_in_json() {
+        let frame = PixelFrame::packed(&[RED, BLUE]);
+        let json = framing::encode(Encoding::Json, &frame);
+        assert_eq!(json, b"{\"Packed\":\"/wAAAAD/\"}\n");
+        let decoded: PixelFrame = framing::decode(Encoding::Json, &json[..json.len() - 1]).unwrap();
+        assert_eq!(decoded, frame);
+    }
+
+    #[test]
+    fn malformed_frames_change_nothing() {
+        let frames = [
+            PixelFrame::Packed(vec![1, 2]),
+            PixelFrame::RunLength(vec![1, 2, 3]),
+            PixelFrame::RunLength([255, 1, 2, 3].repeat(MAX_PIXELS / MAX_RUN + 1)),
+            PixelFrame::Delta(vec![]),
+            PixelFrame::Delta(vec![0x80, 0x80, 0x80, 0x01]),
+            // 2 pixels, change 1 after skipping 2
+            PixelFrame::Delta(vec![2, 2, 1, 1, 2, 3]),
+            // cut short
+            PixelFrame::Delta(vec![2, 0, 2, 1, 2, 3, 4]),
+        ];
+        for frame in frames {
+            let mut pixels = vec![RED; 3];
+            let err = frame.apply(&mut pixels).unwrap_err();
+            assert_eq!(err.code, ErrorCode::MalformedRequest, "{frame:?}");
+            assert_eq!(pixels, [RED; 3]);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn frames_round_trip(previous in any_pixels(), pixels in any_pixels()) {
+            for frame in [
+                PixelFrame::packed(&pixels),
+                PixelFrame::run_length(&pixels),
+                PixelFrame::delta(&previous, &pixels),
+                PixelFrame::encode(Some(&previous), &pixels),
+            ] {
+                prop_assert_eq!(&frame.decode(&previous).unwrap(), &pixels);
+                for encoding in [Encoding::Json, Encoding::Binary] {
+                    let bytes = framing::encode(encoding, &frame);
+                    let bytes = match encoding {
+                        Encoding::Binary => &bytes[1..bytes.len() - 1],
+                        _ => &bytes[..bytes.len() - 1],
+                    };
+                    prop_assert_eq!(&framing::decode::<PixelFrame>(encoding, bytes).unwrap(), &frame);
+                }
+            }
+        }
+
+        #[test]
+        fn any_bytes_decode_without_panic(data in prop::collection::vec(any::<u8>(), 0..64)) {
+            let mut pixels = vec![RED; 8];
+            for frame in [
+                PixelFrame::Packed(data.clone()),
+                PixelFrame::RunLength(data.clone()),
+                PixelFrame::Delta(data.clone()),
+            ] {
+                let _ = frame.apply(&mut pixels);
+            }
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! JSON Schema of the JSON frames, for clients that don't use this crate.
+//!
+//! The generated schema is checked in as `schema/protocol.schema.json`, next to golden frames in
+//! `tests/fixtures`. Frames validate against the root schema: a [`RequestFrame`] or the
+//! [`Response`] to one of the requests. `x-responses` maps every request to the schema of its
+//! reply, [`Request::StreamColor`](crate::Request::StreamColor) has none,
+//! [`Request::InZone`](crate::Request::InZone) and
+//! [`Request::Authorized`](crate::Request::Authorized) are answered like the request they wrap.
+//! Events are replies to [`Request::Subscribe`](crate::Request::Subscribe).
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use schemars::{generate::SchemaSettings, json_schema, JsonSchema, Schema, SchemaGenerator};
+
+use crate::command::{self, Command};
+use crate::{RequestFrame, Response, PROTOCOL_VERSION};
+
+/// Schema of the JSON frames of [`PROTOCOL_VERSION`]
+pub fn protocol_schema() -> Schema {
+    let mut generator = SchemaSettings::draft2020_12().into_generator();
+    let request = generator.subschema_for::<RequestFrame>();
+
+    // every command, named after its request
+    macro_rules! responses {
+        ($($command:ident),+) => {
+            [$((stringify!($command), response::<command::$command>(&mut generator))),+]
+        };
+    }
+    let responses = responses![
+        Hello,
+        GetEffects,
+        GetEffect,
+        GetParameters,
+        GetName,
+        SetEffect,
+        SetOption,
+        SetOptions,
+        Subscribe,
+        GetDeviceInfo,
+        SetPower,
+        SetBrightness,
+        GetPowerState,
+        ListEffects,
+        SetEffectById,
+        GetEffectId,
+        GetZones,
+        StartPairing,
+        Pair
+    ];
+
+    // several requests share a reply type
+    let mut frames = Vec::from([request]);
+    for (_, schema) in responses.iter() {
+        if !frames.contains(schema) {
+            frames.push(schema.clone());
+        }
+    }
+    let responses: serde_json::Map<String, serde_json::Value> = responses
+        .into_iter()
+        .map(|(request, schema)| (request.to_string(), schema.to_value()))
+        .collect();
+
+    json_schema!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": format!("espled protocol {PROTOCOL_VERSION}"),
+        "description": "One JSON frame, without the newline and the optional *XXXX checksum",
+        "anyOf": frames,
+        "x-responses": responses,
+        "$defs": generator.take_definitions(true),
+    })
+}
+
+fn response<C: Command>(generator: &mut SchemaGenerator) -> Schema
+where
+    C::Response: JsonSchema,
+{
+    generator.subschema_for::<Response<C::Response>>()
+}
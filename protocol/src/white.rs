@@ -0,0 +1,229 @@
+//! Colours for strips with dedicated white dies.
+//!
+//! Effects keep rendering [`RGBLedColor`], the white part is extracted right before output.
+//! A white die never looks like pure RGB white, so extraction takes a white point: the colour
+//! the die shows at full duty, e.g. [`RGBLedColor::from_kelvin`] of its rated temperature.
+
+use serde::{Deserialize, Serialize};
+
+use crate::RGBLedColor;
+
+/// Blends of the warm and cold white points tried by [`RGBWWLedColor::from_rgb`]
+const WHITE_MIX_STEPS: u32 = 16;
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RGBWLedColor {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+    pub white: u8,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RGBWWLedColor {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+    pub warm_white: u8,
+    pub cold_white: u8,
+}
+
+/// Duty of a white die that looks like `white_point` which fits into `color`, and what is left of `color`
+fn extract_white(color: RGBLedColor, white_point: RGBLedColor) -> (u8, RGBLedColor) {
+    if white_point == RGBLedColor::default() {
+        return (0, color);
+    }
+
+    let fit = |value: u8, point: u8| match point {
+        0 => u32::MAX,
+        point => value as u32 * 255 / point as u32,
+    };
+    let white = fit(color.red, white_point.red)
+        .min(fit(color.green, white_point.green))
+        .min(fit(color.blue, white_point.blue))
+        .min(255);
+
+    let rest =
+        |value: u8, point: u8| value.saturating_sub(((white * point as u32 + 127) / 255) as u8);
+    (
+        white as u8,
+        RGBLedColor::new(
+            rest(color.red, white_point.red),
+            rest(color.green, white_point.green),
+            rest(color.blue, white_point.blue),
+        ),
+    )
+}
+
+/// `color` with a white die at `duty` added on top
+fn add_white(color: RGBLedColor, duty: u8, white_point: RGBLedColor) -> RGBLedColor {
+    let add = |value: u8, point: u8| {
+        value.saturating_add(((duty as u32 * point as u32 + 127) / 255) as u8)
+    };
+    RGBLedColor::new(
+        add(color.red, white_point.red),
+        add(color.green, white_point.green),
+        add(color.blue, white_point.blue),
+    )
+}
+
+impl RGBWLedColor {
+    pub const fn new(red: u8, green: u8, blue: u8, white: u8) -> Self {
+        Self {
+            red,
+            green,
+            blue,
+            white,
+        }
+    }
+
+    /// Moves as much of `color` as possible to a white die that looks like `white_point`
+    pub fn from_rgb(color: RGBLedColor, white_point: RGBLedColor) -> Self {
+        let (white, rest) = extract_white(color, white_point);
+        Self::new(rest.red, rest.green, rest.blue, white)
+    }
+
+    /// Colour shown by the strip, the inverse of [`RGBWLedColor::from_rgb`] up to rounding
+    pub fn to_rgb(&self, white_point: RGBLedColor) -> RGBLedColor {
+        add_white(
+            RGBLedColor::new(self.red, self.green, self.blue),
+            self.white,
+            white_point,
+        )
+    }
+}
+
+/// Extraction for a pure white die
+impl From<RGBLedColor> for RGBWLedColor {
+    fn from(color: RGBLedColor) -> Self {
+        Self::from_rgb(color, RGBLedColor::new(255, 255, 255))
+    }
+}
+
+impl RGBWWLedColor {
+    pub const fn new(red: u8, green: u8, blue: u8, warm_white: u8, cold_white: u8) -> Self {
+        Self {
+            red,
+            green,
+            blue,
+            warm_white,
+            cold_white,
+        }
+    }
+
+    /// Moves as much of `color` as possible to the white dies. Whites between the two
+    /// temperatures are mixed from both, the blend leaving the least for the RGB dies wins
+    pub fn from_rgb(color: RGBLedColor, warm_point: RGBLedColor, cold_point: RGBLedColor) -> Self {
+        let mix = |warm: u8, cold: u8, step: u32| {
+            ((warm as u32 * (WHITE_MIX_STEPS - step) + cold as u32 * step) / WHITE_MIX_STEPS) as u8
+        };
+
+        let (step, white, rest) = (0..=WHITE_MIX_STEPS)
+            .map(|step| {
+                let white_point = RGBLedColor::new(
+                    mix(warm_point.red, cold_point.red, step),
+                    mix(warm_point.green, cold_point.green, step),
+                    mix(warm_point.blue, cold_point.blue, step),
+                );
+                let (white, rest) = extract_white(color, white_point);
+                (step, white as u32, rest)
+            })
+            .min_by_key(|(_, _, rest)| rest.red as u32 + rest.green as u32 + rest.blue as u32)
+            .expect("at least one blend is tried");
+
+        Self::new(
+            rest.red,
+            rest.green,
+            rest.blue,
+            (white * (WHITE_MIX_STEPS - step) / WHITE_MIX_STEPS) as u8,
+            (white * step / WHITE_MIX_STEPS) as u8,
+        )
+    }
+
+    /// Colour shown by the strip, the inverse of [`RGBWWLedColor::from_rgb`] up to rounding
+    pub fn to_rgb(&self, warm_point: RGBLedColor, cold_point: RGBLedColor) -> RGBLedColor {
+        let color = RGBLedColor::new(self.red, self.green, self.blue);
+        add_white(
+            add_white(color, self.warm_white, warm_point),
+            self.cold_white,
+            cold_point,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn any_color() -> impl Strategy<Value = RGBLedColor> {
+        any::<(u8, u8, u8)>().prop_map(|(red, green, blue)| RGBLedColor::new(red, green, blue))
+    }
+
+    fn assert_close(actual: RGBLedColor, expected: RGBLedColor, tolerance: u8) {
+        assert!(
+            actual.red.abs_diff(expected.red) <= tolerance
+                && actual.green.abs_diff(expected.green) <= tolerance
+                && actual.blue.abs_diff(expected.blue) <= tolerance,
+            "{actual:?} is not close to {expected:?}"
+        );
+    }
+
+    #[test]
+    fn pure_white_die() {
+        assert_eq!(
+            RGBWLedColor::from(RGBLedColor::new(255, 255, 255)),
+            RGBWLedColor::new(0, 0, 0, 255)
+        );
+        assert_eq!(
+            RGBWLedColor::from(RGBLedColor::new(255, 128, 0)),
+            RGBWLedColor::new(255, 128, 0, 0)
+        );
+        assert_eq!(
+            RGBWLedColor::from(RGBLedColor::new(200, 100, 50)),
+            RGBWLedColor::new(150, 50, 0, 50)
+        );
+    }
+
+    #[test]
+    fn black_white_point_is_ignored() {
+        let color = RGBLedColor::new(10, 20, 30);
+        assert_eq!(
+            RGBWLedColor::from_rgb(color, RGBLedColor::default()),
+            RGBWLedColor::new(10, 20, 30, 0)
+        );
+    }
+
+    #[test]
+    fn dual_white_picks_closest_die() {
+        let warm = RGBLedColor::from_kelvin(2700);
+        let cold = RGBLedColor::from_kelvin(6500);
+
+        let color = RGBWWLedColor::from_rgb(warm, warm, cold);
+        assert_eq!(color.warm_white, 255);
+        assert_eq!(color.cold_white, 0);
+
+        let color = RGBWWLedColor::from_rgb(cold, warm, cold);
+        assert_eq!(color.warm_white, 0);
+        assert_eq!(color.cold_white, 255);
+
+        let color = RGBWWLedColor::from_rgb(RGBLedColor::from_kelvin(4000), warm, cold);
+        assert!(color.warm_white > 0 && color.cold_white > 0);
+    }
+
+    proptest! {
+        #[test]
+        fn rgbw_round_trip(color in any_color(), white_point in any_color()) {
+            let rgbw = RGBWLedColor::from_rgb(color, white_point);
+            assert_close(rgbw.to_rgb(white_point), color, 1);
+        }
+
+        #[test]
+        fn rgbww_round_trip(color in any_color(), warm in 1000u16..4000, cold in 5000u16..10000) {
+            let warm = RGBLedColor::from_kelvin(warm);
+            let cold = RGBLedColor::from_kelvin(cold);
+            let rgbww = RGBWWLedColor::from_rgb(color, warm, cold);
+            assert_close(rgbww.to_rgb(warm, cold), color, 3);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+//! Independent outputs of one controller, each with its own effect and parameters.
+//!
+//! Effect, parameter and colour stream requests address a zone by being wrapped in
+//! [`Request::InZone`](crate::Request::InZone), see
+//! [`Request::accepts_zone`](crate::Request::accepts_zone). Everything else, e.g. power and
+//! brightness, applies to the whole controller. Requests that aren't wrapped address
+//! [`DEFAULT_ZONE`], so clients that don't know about zones keep working. Events are about
+//! [`DEFAULT_ZONE`] as well.
+
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+use serde::{Deserialize, Serialize};
+
+pub type ZoneId = u8;
+
+/// Zone of requests outside of [`Request::InZone`](crate::Request::InZone), every controller has it
+pub const DEFAULT_ZONE: ZoneId = 0;
+
+/// Reply to [`Request::GetZones`](crate::Request::GetZones), one per zone
+#[cfg(feature = "alloc")]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ZoneInfo {
+    pub id: ZoneId,
+    /// Shown to the user
+    pub name: String,
+    pub channels: ChannelLayout,
+}
+
+/// LED channels a zone drives. New layouts are appended to the end
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum ChannelLayout {
+    Rgb,
+    /// RGB and one white channel, see [`RGBWLedColor`](crate::RGBWLedColor)
+    Rgbw,
+    /// RGB, warm and cold white, see [`RGBWWLedColor`](crate::RGBWWLedColor)
+    Rgbww,
+}
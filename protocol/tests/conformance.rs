@@ -0,0 +1,416 @@
+//! Golden frames and the JSON Schema, see [`protocol::schema`].
+//!
+//! A failure here means the wire format changed. If that is intended, bump `PROTOCOL_VERSION`
+//! and rewrite the files with `UPDATE_FIXTURES=1 cargo test --features schema --test conformance`.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use protocol::{
+    framing::{self, Encoding, FrameDecoder},
+    schema::protocol_schema,
+    Capability, ChannelLayout, ColorStop, DeviceInfo, EffectInfo, Error, ErrorCode, Event,
+    EventKind, Interpolation, MacAddress, NvsUsage, Palette, ParameterDescriptor, ParameterTypes,
+    PowerState, ProtocolVersion, RGBLedColor, Request, RequestFrame, ResetReason, Response, Token,
+    VersionInfo, ZoneInfo,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+const SCHEMA_PATH: &str = "schema/protocol.schema.json";
+const FIXTURES_PATH: &str = "tests/fixtures";
+
+fn update() -> bool {
+    std::env::var_os("UPDATE_FIXTURES").is_some()
+}
+
+fn path(relative: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(relative)
+}
+
+fn schema_text() -> String {
+    let mut text = serde_json::to_string_pretty(&protocol_schema()).unwrap();
+    text.push('\n');
+    text
+}
+
+/// Every fixture is checked in both encodings: `<name>.json` and `<name>.bin`, complete frames
+/// including delimiters
+struct Fixtures {
+    validator: jsonschema::Validator,
+    names: BTreeSet<String>,
+    failures: Vec<String>,
+}
+
+impl Fixtures {
+    fn new() -> Self {
+        let schema = serde_json::from_str(&schema_text()).unwrap();
+        Self {
+            validator: jsonschema::validator_for(&schema).expect("schema is valid"),
+            names: BTreeSet::new(),
+            failures: Vec::new(),
+        }
+    }
+
+    fn check<T: Serialize + DeserializeOwned>(&mut self, name: &str, message: &T) {
+        assert!(self.names.insert(name.into()), "duplicate fixture {name}");
+
+        for (encoding, extension) in [(Encoding::Json, "json"), (Encoding::Binary, "bin")] {
+            let file = format!("{name}.{extension}");
+            let path = path(FIXTURES_PATH).join(&file);
+            let frame = framing::encode(encoding, message);
+            if update() {
+                fs::write(&path, &frame).unwrap();
+                continue;
+            }
+
+            let Ok(golden) = fs::read(&path) else {
+                self.failures.push(format!("{file}: missing"));
+                continue;
+            };
+            if golden != frame {
+                self.failures.push(format!(
+                    "{file}: encoded differently\n  golden:  {}\n  encoded: {}",
+                    show(encoding, &golden),
+                    show(encoding, &frame),
+                ));
+                continue;
+            }
+
+            // the golden frame has to decode as well, and to the same message
+            let mut decoder = FrameDecoder::new();
+            let frames: Vec<_> = golden
+                .iter()
+                .filter_map(|&byte| decoder.push(byte))
+                .collect();
+            let [(decoded_encoding, contents)] = frames.as_slice() else {
+                self.failures.push(format!("{file}: not a single frame"));
+                continue;
+            };
+            assert_eq!(*decoded_encoding, encoding);
+            match framing::decode::<T>(encoding, contents) {
+                Ok(decoded) if framing::encode(encoding, &decoded) == golden => {}
+                Ok(_) => self
+                    .failures
+                    .push(format!("{file}: decodes to another message")),
+                Err(err) => self.failures.push(format!("{file}: {err}")),
+            }
+
+            if encoding == Encoding::Json {
+                let value = serde_json::from_slice(contents).unwrap();
+                for error in self.validator.iter_errors(&value) {
+                    self.failures
+                        .push(format!("{file}: {error} at {}", error.instance_path));
+                }
+            }
+        }
+    }
+
+    /// Fails on files without a fixture, so removed messages don't linger in the spec
+    fn finish(mut self) {
+        for entry in fs::read_dir(path(FIXTURES_PATH)).unwrap() {
+            let file = entry.unwrap().path();
+            let name = file.file_stem().unwrap().to_string_lossy();
+            if !self.names.contains(name.as_ref()) {
+                self.failures
+                    .push(format!("{}: no fixture", file.display()));
+            }
+        }
+        assert!(
+            self.failures.is_empty(),
+            "wire format changed:\n{}",
+            self.failures.join("\n")
+        );
+    }
+}
+
+fn show(encoding: Encoding, frame: &[u8]) -> String {
+    match encoding {
+        Encoding::Binary => frame.iter().map(|byte| format!("{byte:02x}")).collect(),
+        _ => String::from_utf8_lossy(frame).trim_end().into(),
+    }
+}
+
+fn parameters() -> Vec<(&'static str, ParameterTypes)> {
+    vec![
+        (
+            "color",
+            ParameterTypes::Color(RGBLedColor::new(255, 128, 0)),
+        ),
+        ("float", ParameterTypes::Float(0.5)),
+        ("int", ParameterTypes::Int(-3)),
+        ("bool", ParameterTypes::Bool(true)),
+        (
+            "choice",
+            ParameterTypes::Choice {
+                selected: 1,
+                options: vec!["Forward".into(), "Backward".into()],
+            },
+        ),
+        ("text", ParameterTypes::Text("Hello".into())),
+        (
+            "palette",
+            ParameterTypes::Palette(Palette::new(
+                vec![
+                    ColorStop::new(0.0, RGBLedColor::new(255, 0, 0)),
+                    ColorStop::new(1.0, RGBLedColor::new(0, 0, 255)),
+                ],
+                Interpolation::Stepped,
+            )),
+        ),
+        ("kelvin", ParameterTypes::Kelvin(2700)),
+    ]
+}
+
+fn descriptor() -> ParameterDescriptor {
+    ParameterDescriptor {
+        key: "speed".into(),
+        label: "Speed".into(),
+        value: ParameterTypes::Float(1.5),
+        default: ParameterTypes::Float(1.0),
+        min: Some(0.25),
+        max: Some(4.0),
+        step: Some(0.25),
+        unit: Some("x".into()),
+        group: None,
+    }
+}
+
+fn request(name: &str, request: Request, fixtures: &mut Fixtures) {
+    fixtures.check(&format!("request_{name}"), &RequestFrame { id: 1, request });
+}
+
+fn response<T: Serialize + DeserializeOwned>(
+    name: &str,
+    result: Result<T, Error>,
+    fixtures: &mut Fixtures,
+) {
+    fixtures.check(&format!("response_{name}"), &Response::new(Some(1), result));
+}
+
+#[test]
+fn frames_match_fixtures() {
+    let mut fixtures = Fixtures::new();
+
+    request("hello", Request::Hello, &mut fixtures);
+    request("get_effects", Request::GetEffects, &mut fixtures);
+    request("get_effect", Request::GetEffect, &mut fixtures);
+    request("get_parameters", Request::GetParameters, &mut fixtures);
+    request("get_name", Request::GetName, &mut fixtures);
+    request("set_effect", Request::SetEffect(2), &mut fixtures);
+    for (name, value) in parameters() {
+        let request_name = format!("set_option_{name}");
+        request(
+            &request_name,
+            Request::SetOption(name.into(), value),
+            &mut fixtures,
+        );
+    }
+    request(
+        "set_options",
+        Request::SetOptions(BTreeMap::from([
+            ("brightness".into(), ParameterTypes::Float(0.75)),
+            ("speed".into(), ParameterTypes::Int(4)),
+        ])),
+        &mut fixtures,
+    );
+    request(
+        "subscribe",
+        Request::Subscribe(EventKind::ALL.to_vec()),
+        &mut fixtures,
+    );
+    request(
+        "stream_color",
+        Request::StreamColor {
+            color: RGBLedColor::new(16, 32, 64),
+            timeout_ms: 500,
+        },
+        &mut fixtures,
+    );
+    request("get_device_info", Request::GetDeviceInfo, &mut fixtures);
+    request("set_power", Request::SetPower(false), &mut fixtures);
+    request(
+        "set_brightness",
+        Request::SetBrightness(0.25),
+        &mut fixtures,
+    );
+    request("get_power_state", Request::GetPowerState, &mut fixtures);
+    request("list_effects", Request::ListEffects, &mut fixtures);
+    request(
+        "set_effect_by_id",
+        Request::SetEffectById("hue_rotate".into()),
+        &mut fixtures,
+    );
+    request("get_effect_id", Request::GetEffectId, &mut fixtures);
+    request("get_zones", Request::GetZones, &mut fixtures);
+    request(
+        "in_zone",
+        Request::InZone(1, Box::new(Request::SetEffectById("decay".into()))),
+        &mut fixtures,
+    );
+    request("start_pairing", Request::StartPairing, &mut fixtures);
+    request("pair", Request::Pair(48213), &mut fixtures);
+    request(
+        "authorized",
+        Request::Authorized(Token([0xa5; 16]), Box::new(Request::GetName)),
+        &mut fixtures,
+    );
+
+    response(
+        "hello",
+        Ok(VersionInfo {
+            protocol_version: ProtocolVersion { major: 1, minor: 0 },
+            firmware_version: "0.1.0".into(),
+            capabilities: vec![Capability::ColorParameter, Capability::BinaryFraming],
+        }),
+        &mut fixtures,
+    );
+    response(
+        "get_effects",
+        Ok(vec![String::from("Direct"), String::from("Rainbow")]),
+        &mut fixtures,
+    );
+    response("get_effect", Ok(String::from("Rainbow")), &mut fixtures);
+    response("get_parameters", Ok(vec![descriptor()]), &mut fixtures);
+    response("get_name", Ok(String::from("Desk")), &mut fixtures);
+    response("set_effect", Ok(()), &mut fixtures);
+    response("set_option", Ok(ParameterTypes::Int(10)), &mut fixtures);
+    response(
+        "set_options",
+        Ok(BTreeMap::from([
+            (String::from("brightness"), Ok(ParameterTypes::Float(0.75))),
+            (
+                String::from("speed"),
+                Err(Error::new(ErrorCode::OutOfRange, "at most 3")),
+            ),
+        ])),
+        &mut fixtures,
+    );
+    response(
+        "get_device_info",
+        Ok(DeviceInfo {
+            firmware_version: "0.1.0".into(),
+            git_hash: Some("4a1b2c3d5e-dirty".into()),
+            build_date: "2026-10-18T09:30:00Z".into(),
+            chip_model: "ESP32-C3".into(),
+            chip_revision: 4,
+            mac_address: MacAddress([0x34, 0x85, 0x18, 0x01, 0x02, 0x03]),
+            uptime_ms: 86_400_000,
+            free_heap: 210_000,
+            min_free_heap: 180_000,
+            nvs: Some(NvsUsage {
+                used_entries: 40,
+                total_entries: 756,
+            }),
+            reset_reason: ResetReason::Brownout,
+        }),
+        &mut fixtures,
+    );
+    response("set_power", Ok(false), &mut fixtures);
+    response("set_brightness", Ok(0.25f32), &mut fixtures);
+    response(
+        "get_power_state",
+        Ok(PowerState {
+            on: true,
+            brightness: 0.75,
+        }),
+        &mut fixtures,
+    );
+    response(
+        "list_effects",
+        Ok(vec![
+            EffectInfo {
+                id: "direct".into(),
+                name: "Direct".into(),
+            },
+            EffectInfo {
+                id: "hue_rotate".into(),
+                name: "Hue Rotate".into(),
+            },
+        ]),
+        &mut fixtures,
+    );
+    response("set_effect_by_id", Ok(()), &mut fixtures);
+    response(
+        "get_effect_id",
+        Ok(String::from("hue_rotate")),
+        &mut fixtures,
+    );
+    response(
+        "get_zones",
+        Ok(vec![
+            ZoneInfo {
+                id: 0,
+                name: "Desk".into(),
+                channels: ChannelLayout::Rgbw,
+            },
+            ZoneInfo {
+                id: 1,
+                name: "Shelf".into(),
+                channels: ChannelLayout::Rgb,
+            },
+        ]),
+        &mut fixtures,
+    );
+    response("pair", Ok(Token([0xa5; 16])), &mut fixtures);
+    response(
+        "error",
+        Err::<(), _>(Error::new(ErrorCode::UnknownEffect, "no effect 9")),
+        &mut fixtures,
+    );
+    fixtures.check(
+        "response_malformed",
+        &Response::<()>::new(
+            None,
+            Err(Error::new(ErrorCode::MalformedRequest, "expected value")),
+        ),
+    );
+
+    let events = [
+        ("subscribed", Event::Subscribed(vec![EventKind::Effect])),
+        (
+            "effect_changed",
+            Event::EffectChanged {
+                id: "rainbow".into(),
+                name: "Rainbow".into(),
+                parameters: vec![descriptor()],
+            },
+        ),
+        (
+            "parameter_changed",
+            Event::ParameterChanged {
+                key: "speed".into(),
+                value: ParameterTypes::Float(2.0),
+            },
+        ),
+        ("power_changed", Event::PowerChanged(false)),
+        ("brightness_changed", Event::BrightnessChanged(0.5)),
+        (
+            "error",
+            Event::Error(Error::new(ErrorCode::OutputFailure, "ledc")),
+        ),
+    ];
+    for (name, event) in events {
+        response(&format!("event_{name}"), Ok(event), &mut fixtures);
+    }
+
+    fixtures.finish();
+}
+
+#[test]
+fn schema_is_up_to_date() {
+    let path = path(SCHEMA_PATH);
+    let schema = schema_text();
+    if update() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, schema).unwrap();
+        return;
+    }
+    let golden = fs::read_to_string(&path).unwrap_or_default();
+    assert!(
+        golden == schema,
+        "{SCHEMA_PATH} is out of date, run with UPDATE_FIXTURES=1"
+    );
+}